@@ -35,3 +35,16 @@ build_and_copy() {
 for project in "${PROJECTS[@]}"; do
     build_and_copy "$project"
 done
+
+# Precompress text assets so the server can send them without compressing on every request
+COMPRESSIBLE=(-name "*.js" -o -name "*.css" -o -name "*.html" -o -name "*.svg" -o -name "*.json")
+
+if command -v gzip >/dev/null; then
+    echo "Precompressing static files with gzip..."
+    find "$STATIC_DIR" -type f \( "${COMPRESSIBLE[@]}" \) -exec gzip -k -f -9 {} \;
+fi
+
+if command -v brotli >/dev/null; then
+    echo "Precompressing static files with brotli..."
+    find "$STATIC_DIR" -type f \( "${COMPRESSIBLE[@]}" \) -exec brotli -k -f -q 11 {} \;
+fi
@@ -23,13 +23,23 @@ interface Config<T> {
   default: T;
 }
 
+// Lists and objects are passed to the plugin as JSON
+function attributeValue(value: unknown): string {
+  return typeof value === "string" ? value : JSON.stringify(value);
+}
+
 interface Script {
   url?: string;
   inline?: string;
 }
 
 interface Plugin {
+  // The plugin name for the default instance, `name:N` for the others
+  id: string;
   name: string;
+  label?: string;
+  // The position set by the user, if any
+  order?: number;
   version: string;
   enabled: boolean;
   description?: string;
@@ -39,13 +49,21 @@ interface Plugin {
   backgroundScript?: Script;
 }
 
+// Plugins without a position come last
+function byOrder(a: Plugin, b: Plugin): number {
+  const position = (p: Plugin) => p.order ?? Number.MAX_SAFE_INTEGER;
+  return position(a) - position(b) || a.id.localeCompare(b.id);
+}
+
 class Display {
   ws: WebSocketClient;
-  _plugins: { [name: string]: Plugin } = {};
+  _plugins: { [id: string]: Plugin } = {};
   host: string;
   selected?: Plugin;
   callback?: (mesg: Message) => void;
   _style: string | null = null;
+  // The script URL each custom element was defined from
+  loadedScripts: { [name: string]: string } = {};
 
   get style() {
     return this._style;
@@ -72,7 +90,7 @@ class Display {
   }
 
   get plugins() {
-    return Object.values(this._plugins);
+    return Object.values(this._plugins).sort(byOrder);
   }
 
   set plugins(plugins: Array<Plugin>) {
@@ -80,11 +98,12 @@ class Display {
     plugins
       .map((p) => {
         p.enabled = p.configs.find((c) => c.name == "enabled")!.value;
-        p.script.url = "http://" + this.host + p.script.url;
+        if (p.script.url?.startsWith("/"))
+          p.script.url = "http://" + this.host + p.script.url;
         return p;
       })
       .forEach((p) => {
-        if (p.enabled) this._plugins[p.name] = p;
+        if (p.enabled) this._plugins[p.id] = p;
       });
 
     this.updateDOM();
@@ -112,8 +131,13 @@ class Display {
     }
 
     if (!this.selected) this.selected = this.plugins[0];
-    // Update selected
-    this.selected = this._plugins[this.selected.name];
+    // Update selected, falling back to the first one if it was removed
+    this.selected = this._plugins[this.selected.id] || this.plugins[0];
+
+    // A custom element can't be redefined, reload to run the reinstalled script
+    const loaded = this.loadedScripts[this.selected.name];
+    if (loaded && loaded !== this.selected.script.url)
+      return window.location.reload();
 
     // Change changes
     const info = document.querySelector(".info");
@@ -124,13 +148,16 @@ class Display {
 
       // Check if only the config changes
       const parsed = JSON.parse(info.innerHTML);
-      if (parsed.name === this.selected.name) {
+      if (parsed.id === this.selected.id) {
         const webComponent = document.querySelector(this.selected.name);
         if (webComponent) {
           info.innerHTML = stringified;
           for (const config of this.selected.configs) {
             if (config.name !== "enabled" && config.value !== null)
-              webComponent.setAttribute(config.name, String(config.value));
+              webComponent.setAttribute(
+                config.name,
+                attributeValue(config.value)
+              );
           }
         }
 
@@ -149,13 +176,17 @@ class Display {
 
     const scriptElement = document.createElement("script");
     scriptElement.src = this.selected.script.url!;
+    this.loadedScripts[this.selected.name] = scriptElement.src;
     scriptElement.setAttribute("type", "module");
     document.body.appendChild(scriptElement);
 
     const webComponent = document.createElement(this.selected.name);
     for (const config of this.selected.configs) {
       if (config.name !== "enabled" && config.value !== null)
-        webComponent.setAttribute(config.name, String(config.value));
+        webComponent.setAttribute(
+          config.name,
+          attributeValue(config.value)
+        );
     }
 
     if (this.selected.backgroundScript?.url) {
@@ -187,7 +218,7 @@ class Display {
     }
 
     const currentIndex = plugins.findIndex(
-      (p) => p.name === this.selected!.name
+      (p) => p.id === this.selected!.id
     );
     if (currentIndex === -1) return;
 
@@ -210,7 +241,19 @@ class Display {
         break;
       case "removePlugin":
         let name = mesg.data.name as string;
-        this.plugins = this.plugins.filter((p) => p.name !== name);
+        let id = mesg.data.id as string | undefined;
+        // Without an id, the plugin is removed along with all its instances
+        this.plugins = this.plugins.filter((p) =>
+          id ? p.id !== id : p.name !== name
+        );
+        break;
+
+      case "reorderPlugins":
+        const order = mesg.data.order as Array<string>;
+        for (const p of Object.values(this._plugins)) {
+          const index = order.indexOf(p.id);
+          p.order = index === -1 ? undefined : index;
+        }
         break;
 
       case "pluginMessage":
@@ -229,6 +272,11 @@ class Display {
         this.style = null;
         break;
 
+      case "applyScene":
+        this.style = mesg.data.style?.url || null;
+        this.plugins = mesg.data.plugins as Array<Plugin>;
+        break;
+
       case "error":
         console.error("Error:", mesg.data);
         break;
@@ -0,0 +1,323 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::fetch::{fetch_bytes, read_file, resolve_url};
+use crate::paths::{data_path, DATA_DIR};
+use crate::serve::sha256_hex;
+use crate::{Message, MessageType, Server};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use url::Url;
+use ws::Result;
+
+/// The catalog used when no sources are configured.
+const DEFAULT_SOURCE: &str =
+    "https://raw.githubusercontent.com/nohackjustnoobb/RPi-WidgetBox-Plugins/main/index.json";
+/// How long a cached catalog is used before it is downloaded again.
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// The largest catalog accepted.
+const MAX_CATALOG_SIZE: u64 = 4 * 1024 * 1024;
+
+/// A plugin listed in a catalog.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    /// The URL of the plugin's meta, relative to the catalog.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    /// The URL of the plugin's package, relative to the catalog. Takes precedence over
+    /// `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    package: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// The catalog the entry was read from.
+    #[serde(default)]
+    pub source: String,
+}
+
+impl CatalogEntry {
+    /// Returns the data of an `addPlugin` message installing this entry.
+    pub fn install_data(&self) -> Option<Value> {
+        match (&self.package, &self.url) {
+            (Some(package), _) => Some(json!({ "package": { "url": package } })),
+            (None, Some(url)) => Some(json!({ "url": url })),
+            (None, None) => None,
+        }
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+
+        self.name.to_lowercase().contains(&query)
+            || self
+                .description
+                .as_ref()
+                .is_some_and(|d| d.to_lowercase().contains(&query))
+            || self.tags.iter().any(|t| t.to_lowercase() == query)
+    }
+
+    /// Resolves the URLs of the entry against the catalog it was read from.
+    fn resolve(&mut self, base: &Url) -> std::result::Result<(), String> {
+        for url in [&mut self.url, &mut self.package].into_iter().flatten() {
+            *url = resolve_url(Some(base), url)?.to_string();
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct Catalog {
+    plugins: Vec<CatalogEntry>,
+}
+
+/// The contents of `data/catalogs.json`.
+#[derive(Deserialize)]
+struct Sources {
+    sources: Vec<String>,
+}
+
+/// Returns the configured catalog sources. They are read from the comma separated
+/// `WIDGETBOX_CATALOGS` environment variable, then from `data/catalogs.json`.
+fn sources() -> Vec<String> {
+    if let Ok(sources) = env::var("WIDGETBOX_CATALOGS") {
+        return sources
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+    }
+
+    let raw = match data_path("catalogs.json").and_then(|p| fs::read_to_string(p).ok()) {
+        Some(r) => r,
+        None => return vec![DEFAULT_SOURCE.to_string()],
+    };
+
+    match serde_json::from_str::<Sources>(&raw) {
+        Ok(s) => s.sources,
+        Err(_) => {
+            warn!("Failed to parse catalogs.json, using the default catalog.");
+            vec![DEFAULT_SOURCE.to_string()]
+        }
+    }
+}
+
+/// Parses the location of a catalog. Catalogs are configured on the server rather than
+/// by clients, so local catalogs don't need side-loading to be enabled, but they are
+/// never read from the data directory.
+fn catalog_location(source: &str) -> Option<Url> {
+    let url = Url::parse(source).ok();
+    if url
+        .as_ref()
+        .is_some_and(|u| matches!(u.scheme(), "http" | "https"))
+    {
+        return url;
+    }
+
+    let path = match url.filter(|u| u.scheme() == "file") {
+        Some(url) => url.to_file_path().ok()?,
+        None => PathBuf::from(source),
+    };
+    let path = path.canonicalize().ok().filter(|p| p.is_file())?;
+
+    let in_data = Path::new(DATA_DIR)
+        .canonicalize()
+        .is_ok_and(|data| path.starts_with(data));
+    match in_data {
+        true => None,
+        false => Url::from_file_path(path).ok(),
+    }
+}
+
+/// Downloads or reads a catalog and resolves the URLs of its entries.
+fn fetch(source: &str) -> std::result::Result<Vec<CatalogEntry>, String> {
+    let url = catalog_location(source).ok_or(format!("Failed to get catalog {}.", source))?;
+    let raw = match url.to_file_path() {
+        Ok(path) if url.scheme() == "file" => read_file(&path, MAX_CATALOG_SIZE)?,
+        _ => fetch_bytes(url.as_str(), MAX_CATALOG_SIZE)?,
+    };
+
+    let catalog = serde_json::from_slice::<Catalog>(&raw)
+        .map_err(|_| format!("Failed to parse catalog {}.", source))?;
+
+    let mut entries = Vec::new();
+    for mut entry in catalog.plugins {
+        if let Err(e) = entry.resolve(&url) {
+            warn!("Skipping {} in catalog {}: {}", entry.name, source, e);
+            continue;
+        }
+
+        entry.source = source.to_string();
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Reads a catalog, from the local cache if it is recent enough or if the source can't
+/// be reached.
+///
+/// # Parameters
+///
+/// * `source` - The URL or local path of the catalog.
+/// * `refresh` - Whether to ignore a recent cache.
+fn load(source: &str, refresh: bool) -> std::result::Result<Vec<CatalogEntry>, String> {
+    let cache = data_path(&format!(
+        "catalog/{}.json",
+        &sha256_hex(source.as_bytes())[..16]
+    ))
+    .ok_or("Invalid catalog cache.")?;
+
+    let read_cache = || {
+        fs::read(&cache)
+            .ok()
+            .and_then(|raw| serde_json::from_slice::<Vec<CatalogEntry>>(&raw).ok())
+    };
+
+    let cache_age = fs::metadata(&cache)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok());
+
+    if cache_age.is_some_and(|age| !refresh && age < CACHE_TTL) {
+        if let Some(entries) = read_cache() {
+            return Ok(entries);
+        }
+    }
+
+    match fetch(source) {
+        Ok(entries) => {
+            let cached = fs::create_dir_all(cache.parent().unwrap())
+                .and_then(|_| fs::write(&cache, json!(entries).to_string()));
+            if cached.is_err() {
+                warn!("Failed to cache catalog {}.", source);
+            }
+
+            Ok(entries)
+        }
+        Err(e) => match read_cache() {
+            Some(entries) => {
+                warn!("{} Using the cached catalog.", e);
+                Ok(entries)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Reads every configured catalog. Catalogs that can't be read are logged and skipped.
+pub fn entries(refresh: bool) -> Vec<CatalogEntry> {
+    let mut entries = Vec::new();
+
+    for source in sources() {
+        match load(&source, refresh) {
+            Ok(e) => entries.extend(e),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    entries
+}
+
+impl Server {
+    /// Reads every configured catalog on a worker thread, so displays keep being served
+    /// while they are downloaded, and hands the entries to `handle` along with a server
+    /// replying to the same client.
+    fn with_entries<F>(&self, refresh: bool, handle: F) -> Result<()>
+    where
+        F: FnOnce(&Server, Vec<CatalogEntry>) -> Result<()> + Send + 'static,
+    {
+        let out = self.out.clone();
+        thread::spawn(move || {
+            let _ = handle(&Server::new(out), entries(refresh));
+        });
+
+        Ok(())
+    }
+
+    /// Lists the plugins of every configured catalog.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - An optional JSON object with a `refresh` property. If `refresh` is
+    ///   `true`, the catalogs are downloaded again even if they were cached recently.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn list_catalog(&self, data: Value) -> Result<()> {
+        let refresh = data["refresh"].as_bool().unwrap_or(false);
+
+        self.with_entries(refresh, |server, entries| {
+            server.send(Message {
+                type_: MessageType::ListCatalog,
+                data: json!(entries),
+            })
+        })
+    }
+
+    /// Searches the catalogs by name, description and tags.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with a `query` property.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn search_catalog(&self, data: Value) -> Result<()> {
+        let query = match data["query"].as_str() {
+            Some(q) => q.trim().to_string(),
+            None => return self.send(Message::error("Failed to get query.")),
+        };
+
+        self.with_entries(false, move |server, entries| {
+            let results: Vec<CatalogEntry> =
+                entries.into_iter().filter(|e| e.matches(&query)).collect();
+
+            server.send(Message {
+                type_: MessageType::SearchCatalog,
+                data: json!(results),
+            })
+        })
+    }
+
+    /// Installs a plugin listed in a catalog.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with a `name` property, and optionally a `source`
+    ///   property to pick a catalog when several list the plugin.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn install_from_catalog(&self, data: Value) -> Result<()> {
+        let name = match data["name"].as_str() {
+            Some(n) => n.to_string(),
+            None => return self.send(Message::error("Failed to get plugin.")),
+        };
+        let source = data["source"].as_str().map(str::to_string);
+
+        self.with_entries(false, move |server, entries| {
+            let entry = entries
+                .into_iter()
+                .find(|e| e.name == name && source.as_ref().is_none_or(|s| e.source == *s));
+
+            match entry.and_then(|e| e.install_data()) {
+                Some(install) => server.add_plugin(install),
+                None => server.send(Message::error("Plugin not found in catalog.")),
+            }
+        })
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use log::warn;
+
+use crate::serve::content_type;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The extension of the precompressed sibling of a file.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    pub fn sibling(&self, path: &Path) -> PathBuf {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(self.extension());
+        PathBuf::from(sibling)
+    }
+}
+
+/// Whether compressing a content type is worth it. Images, fonts, media and archives
+/// are already compressed.
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/manifest+json"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+                | "image/bmp"
+                | "image/x-icon"
+                | "font/ttf"
+                | "font/otf"
+        )
+}
+
+/// Lists the encodings accepted by an `Accept-Encoding` header, most preferred first.
+pub fn accepted_encodings(accept_encoding: &str) -> Vec<Encoding> {
+    let mut accepted = Vec::new();
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let encoding = match parts.next().unwrap_or("").trim() {
+            "br" => Encoding::Brotli,
+            "gzip" => Encoding::Gzip,
+            _ => continue,
+        };
+
+        let quality = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if quality > 0.0 {
+            accepted.push((encoding, quality));
+        }
+    }
+
+    // Brotli wins ties since it compresses better.
+    accepted.sort_by(|(a, qa), (b, qb)| {
+        qb.total_cmp(qa)
+            .then((*a == Encoding::Gzip).cmp(&(*b == Encoding::Gzip)))
+    });
+
+    accepted.into_iter().map(|(encoding, _)| encoding).collect()
+}
+
+pub fn compress(bytes: &[u8], encoding: Encoding) -> Option<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(bytes).ok()?;
+            encoder.finish().ok()
+        }
+        Encoding::Brotli => {
+            let mut output = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, 11, 22);
+                encoder.write_all(bytes).ok()?;
+            }
+            Some(output)
+        }
+    }
+}
+
+/// Writes `.gz` and `.br` siblings next to every compressible file in a directory, so
+/// they don't have to be compressed on every request. The meta of a plugin is skipped,
+/// since it holds encrypted secrets, changes with every config write and isn't served.
+pub fn precompress_dir(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            precompress_dir(&path);
+        } else if path.file_name().is_some_and(|n| n == "meta.json") {
+            remove_siblings(&path);
+        } else {
+            precompress_file(&path);
+        }
+    }
+}
+
+pub fn precompress_file(path: &Path) {
+    let is_sibling = path
+        .extension()
+        .is_some_and(|ext| ext == "gz" || ext == "br");
+    if is_sibling || !is_compressible(content_type(path)) {
+        return;
+    }
+
+    let bytes = match fs::read(path) {
+        Ok(b) => b,
+        Err(_) => return,
+    };
+
+    for encoding in [Encoding::Brotli, Encoding::Gzip] {
+        let written = compress(&bytes, encoding)
+            .map(|compressed| fs::write(encoding.sibling(path), compressed).is_ok());
+
+        if written != Some(true) {
+            warn!("Failed to precompress {}.", path.display());
+        }
+    }
+}
+
+/// Removes the precompressed siblings of a file.
+pub fn remove_siblings(path: &Path) {
+    for encoding in [Encoding::Brotli, Encoding::Gzip] {
+        let _ = fs::remove_file(encoding.sibling(path));
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::compress::precompress_file;
+use crate::integrity::file_digest;
+use crate::paths::data_path;
+use crate::plugin::{PluginMeta, Script};
+use crate::scenes::lock_switch;
+use crate::style::recorded_integrity;
+use crate::vendor::vendor_modules;
+use crate::{Message, MessageType, Server};
+
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use ws::Result;
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Problem {
+    MissingMeta,
+    InvalidMeta,
+    MissingScript,
+    MissingBackgroundScript,
+    MissingEditorScript,
+    NameMismatch,
+    Modified,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Repair {
+    FetchScript,
+    FetchBackgroundScript,
+    FetchEditorScript,
+    RenameDirectory,
+    /// Moves the directory to `data/quarantine`, so nothing is lost if it was wanted.
+    QuarantineDirectory,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Diagnostic {
+    directory: String,
+    problem: Problem,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repair: Option<Repair>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    meta: Option<PluginMeta>,
+}
+
+impl Diagnostic {
+    fn new(path: &Path, problem: Problem, message: String, repair: Option<Repair>) -> Self {
+        Diagnostic {
+            directory: path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            problem,
+            message,
+            repair,
+            path: path.to_path_buf(),
+            meta: None,
+        }
+    }
+
+    fn with_meta(mut self, meta: &PluginMeta) -> Self {
+        self.meta = Some(meta.clone());
+        self
+    }
+
+    /// Applies the suggested repair, if any.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the problem has been fixed.
+    fn repair(&self) -> bool {
+        // Fetches the script again, rewrites its imports as on install and records its
+        // new hash.
+        let write_script = |meta: &PluginMeta, script: &Script, filename: &str| {
+            let path = self.path.join(filename);
+            let written = script.fetch().is_ok_and(|s| fs::write(&path, s).is_ok())
+                && vendor_modules(&self.path, filename, script.source_url()).is_ok();
+            if !written {
+                return false;
+            }
+            precompress_file(&path);
+
+            let mut meta = meta.clone();
+            meta.record_integrity(&self.path);
+            serde_json::to_string(&meta)
+                .is_ok_and(|raw| fs::write(self.path.join("meta.json"), raw).is_ok())
+        };
+
+        match (self.repair, &self.meta) {
+            (Some(Repair::FetchScript), Some(meta)) => write_script(meta, &meta.script, "index.js"),
+            (Some(Repair::FetchBackgroundScript), Some(meta)) => match &meta.background_script {
+                Some(script) => write_script(meta, script, "background.js"),
+                None => false,
+            },
+            (Some(Repair::FetchEditorScript), Some(meta)) => match &meta.editor_script {
+                Some(script) => write_script(meta, script, "editor.js"),
+                None => false,
+            },
+            (Some(Repair::RenameDirectory), Some(meta)) => {
+                let target = self.path.with_file_name(meta.name.as_str());
+                !target.exists() && fs::rename(&self.path, target).is_ok()
+            }
+            (Some(Repair::QuarantineDirectory), _) => {
+                let seconds = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                let target = data_path(&format!("quarantine/{}-{}", self.directory, seconds));
+
+                target.is_some_and(|target| {
+                    fs::create_dir_all(target.parent().unwrap())
+                        .and_then(|_| fs::rename(&self.path, target))
+                        .is_ok()
+                })
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Inspects a single plugin directory and returns every problem found in it.
+fn inspect(path: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let meta_path = path.join("meta.json");
+    let raw = match fs::read_to_string(&meta_path) {
+        Ok(r) => r,
+        Err(_) => {
+            diagnostics.push(Diagnostic::new(
+                path,
+                Problem::MissingMeta,
+                "The meta file is missing or unreadable.".to_string(),
+                Some(Repair::QuarantineDirectory),
+            ));
+            return diagnostics;
+        }
+    };
+
+    let meta = match serde_json::from_str::<PluginMeta>(&raw) {
+        Ok(m) => m,
+        Err(e) => {
+            diagnostics.push(Diagnostic::new(
+                path,
+                Problem::InvalidMeta,
+                format!("The meta file is invalid: {}.", e),
+                Some(Repair::QuarantineDirectory),
+            ));
+            return diagnostics;
+        }
+    };
+
+    if !path.join("index.js").is_file() {
+        diagnostics.push(
+            Diagnostic::new(
+                path,
+                Problem::MissingScript,
+                "The script file index.js is missing.".to_string(),
+                Some(Repair::FetchScript),
+            )
+            .with_meta(&meta),
+        );
+    }
+
+    if meta.background_script.is_some() && !path.join("background.js").is_file() {
+        diagnostics.push(
+            Diagnostic::new(
+                path,
+                Problem::MissingBackgroundScript,
+                "A background script is declared but background.js is missing.".to_string(),
+                Some(Repair::FetchBackgroundScript),
+            )
+            .with_meta(&meta),
+        );
+    }
+
+    if meta.editor_script.is_some() && !path.join("editor.js").is_file() {
+        diagnostics.push(
+            Diagnostic::new(
+                path,
+                Problem::MissingEditorScript,
+                "An editor script is declared but editor.js is missing.".to_string(),
+                Some(Repair::FetchEditorScript),
+            )
+            .with_meta(&meta),
+        );
+    }
+
+    for (filename, recorded) in meta.integrity.iter() {
+        let file_path = path.join(filename);
+        if !file_path.is_file() || file_digest(&file_path).as_ref() == Some(recorded) {
+            continue;
+        }
+
+        let repair = match filename.as_str() {
+            "index.js" => Some(Repair::FetchScript),
+            "background.js" => Some(Repair::FetchBackgroundScript),
+            "editor.js" => Some(Repair::FetchEditorScript),
+            _ => None,
+        };
+
+        diagnostics.push(
+            Diagnostic::new(
+                path,
+                Problem::Modified,
+                format!("The file {} was modified after it was installed.", filename),
+                repair,
+            )
+            .with_meta(&meta),
+        );
+    }
+
+    if path.file_name().and_then(|n| n.to_str()) != Some(meta.name.as_str()) {
+        let target_exists = path.with_file_name(meta.name.as_str()).exists();
+
+        diagnostics.push(
+            Diagnostic::new(
+                path,
+                Problem::NameMismatch,
+                format!(
+                    "The directory name does not match the plugin name \"{}\".",
+                    meta.name
+                ),
+                if target_exists {
+                    None
+                } else {
+                    Some(Repair::RenameDirectory)
+                },
+            )
+            .with_meta(&meta),
+        );
+    }
+
+    diagnostics
+}
+
+/// Checks the custom stylesheet against the hash recorded when it was saved.
+fn inspect_style() -> Option<Diagnostic> {
+    let path = data_path("style.css")?;
+    let recorded = recorded_integrity()?;
+
+    match file_digest(&path) {
+        Some(digest) if digest != recorded => Some(Diagnostic::new(
+            &path,
+            Problem::Modified,
+            "The custom style was modified after it was saved.".to_string(),
+            None,
+        )),
+        _ => None,
+    }
+}
+
+/// Checks every directory in `data/plugins`, and the custom stylesheet, for problems.
+pub fn check() -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = inspect_style().into_iter().collect();
+
+    let entries = data_path("plugins").and_then(|p| fs::read_dir(p).ok());
+    if let Some(entries) = entries {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                diagnostics.extend(inspect(&path));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Runs the doctor pass once and logs every problem found.
+pub fn startup_check() {
+    let diagnostics = check();
+
+    for diagnostic in diagnostics.iter() {
+        warn!(
+            "Plugin directory \"{}\": {}",
+            diagnostic.directory, diagnostic.message
+        );
+    }
+
+    if !diagnostics.is_empty() {
+        info!(
+            "Send a \"doctor\" message with the directories to `repair` to fix the problems above."
+        );
+    }
+}
+
+impl Server {
+    /// Reports every plugin directory with problems.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - An optional JSON object with a `repair` property, an array of the
+    ///   directories to apply the suggested repairs to, after which they are checked
+    ///   again. Broken directories are moved to `data/quarantine` rather than removed.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn doctor(&self, data: Value) -> Result<()> {
+        let mut diagnostics = check();
+        let mut repaired = Vec::new();
+
+        let selected: Vec<&str> = data["repair"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|d| d.as_str())
+            .collect();
+
+        if !selected.is_empty() {
+            let _guard = lock_switch();
+            // Only one repair per directory per pass, a rename or move invalidates the rest.
+            let mut touched = Vec::new();
+            for diagnostic in diagnostics.iter() {
+                if touched.contains(&diagnostic.path)
+                    || diagnostic.repair.is_none()
+                    || !selected.contains(&diagnostic.directory.as_str())
+                {
+                    continue;
+                }
+
+                if diagnostic.repair() {
+                    info!(
+                        "Repaired plugin directory \"{}\": {}",
+                        diagnostic.directory, diagnostic.message
+                    );
+                    repaired.push(diagnostic.clone());
+
+                    if matches!(
+                        diagnostic.repair,
+                        Some(Repair::RenameDirectory | Repair::QuarantineDirectory)
+                    ) {
+                        touched.push(diagnostic.path.clone());
+                    }
+                }
+            }
+
+            diagnostics = check();
+        }
+
+        self.send(Message {
+            type_: MessageType::Doctor,
+            data: json!({
+                "diagnostics": diagnostics,
+                "repaired": repaired,
+            }),
+        })
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    env, fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use reqwest::blocking::get;
+use url::Url;
+
+use crate::paths::DATA_DIR;
+
+/// The largest script or stylesheet accepted.
+pub const MAX_SCRIPT_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Checks that a local file may be side-loaded. Side-loading is off unless the server
+/// is started with `WIDGETBOX_SIDELOAD_DIR`, and only files inside that directory are
+/// read. Files in the data directory, such as uploads and the secret key, never are.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The canonicalized path, or `None` if it may not be read.
+pub fn sideload_path(path: &Path) -> Option<PathBuf> {
+    let dir = PathBuf::from(env::var("WIDGETBOX_SIDELOAD_DIR").ok()?)
+        .canonicalize()
+        .ok()?;
+    let path = path.canonicalize().ok()?;
+
+    let in_data = Path::new(DATA_DIR)
+        .canonicalize()
+        .is_ok_and(|data| path.starts_with(data));
+    (path.starts_with(dir) && !in_data).then_some(path)
+}
+
+/// Parses the location of a file to install. Anything that isn't an `http` or `https`
+/// URL is treated as a `file` URL or a local path, which allows side-loading plugins
+/// from the directory allowed by `sideload_path`.
+pub fn parse_location(location: &str) -> Option<Url> {
+    let url = Url::parse(location).ok();
+    if url
+        .as_ref()
+        .is_some_and(|u| matches!(u.scheme(), "http" | "https"))
+    {
+        return url;
+    }
+
+    let path = match url.filter(|u| u.scheme() == "file") {
+        Some(url) => url.to_file_path().ok()?,
+        None => PathBuf::from(location),
+    };
+    Url::from_file_path(sideload_path(&path)?).ok()
+}
+
+/// Resolves a URL found in a file downloaded from `base`. Relative URLs are resolved
+/// against `base`, and only files that were themselves loaded from the local disk may
+/// point to other local files that may be side-loaded.
+pub fn resolve_url(base: Option<&Url>, url: &str) -> Result<Url, String> {
+    let resolved = match base {
+        Some(base) => base.join(url),
+        None => Url::parse(url),
+    }
+    .map_err(|_| format!("Invalid URL {}.", url))?;
+
+    let base_is_local = base.is_some_and(|b| b.scheme() == "file");
+    match resolved.scheme() {
+        "http" | "https" => Ok(resolved),
+        "file" if base_is_local => resolved
+            .to_file_path()
+            .ok()
+            .and_then(|p| sideload_path(&p))
+            .and_then(|p| Url::from_file_path(p).ok())
+            .ok_or_else(|| format!("{} may not be side-loaded.", url)),
+        _ => Err(format!("Unsupported URL {}.", url)),
+    }
+}
+
+/// Reads a local file, refusing anything larger than `limit` bytes.
+pub fn read_file(path: &Path, limit: u64) -> Result<Vec<u8>, String> {
+    let len = fs::metadata(path)
+        .map_err(|_| format!("Failed to read {}.", path.display()))?
+        .len();
+    if len > limit {
+        return Err(format!(
+            "{} is larger than {} bytes.",
+            path.display(),
+            limit
+        ));
+    }
+
+    fs::read(path).map_err(|_| format!("Failed to read {}.", path.display()))
+}
+
+/// Downloads a file, refusing anything larger than `limit` bytes. `file` URLs are read
+/// from the local disk, if they may be side-loaded.
+pub fn fetch_bytes(url: &str, limit: u64) -> Result<Vec<u8>, String> {
+    let parsed = Url::parse(url).map_err(|_| format!("Invalid URL {}.", url))?;
+
+    if parsed.scheme() == "file" {
+        let path = parsed
+            .to_file_path()
+            .ok()
+            .and_then(|p| sideload_path(&p))
+            .ok_or_else(|| format!("{} may not be side-loaded.", url))?;
+
+        return read_file(&path, limit);
+    }
+
+    let resp = get(url).map_err(|_| format!("Failed to fetch {}.", url))?;
+
+    if !resp.status().is_success() {
+        return Err(format!("Failed to fetch {} ({}).", url, resp.status()));
+    }
+
+    if resp.content_length().is_some_and(|len| len > limit) {
+        return Err(format!("{} is larger than {} bytes.", url, limit));
+    }
+
+    let mut bytes = Vec::new();
+    resp.take(limit + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|_| format!("Failed to fetch {}.", url))?;
+
+    if bytes.len() as u64 > limit {
+        return Err(format!("{} is larger than {} bytes.", url, limit));
+    }
+
+    Ok(bytes)
+}
+
+/// Downloads a text file, such as a script or a stylesheet.
+pub fn fetch_text(url: &str) -> Result<String, String> {
+    String::from_utf8(fetch_bytes(url, MAX_SCRIPT_SIZE)?)
+        .map_err(|_| format!("{} is not valid UTF-8.", url))
+}
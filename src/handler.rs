@@ -4,7 +4,7 @@ use ws::Result;
 use crate::{Message, MessageType, Server};
 
 impl Server {
-    pub fn handler(&self, type_: MessageType, data: Value) -> Result<()> {
+    pub fn handler(&mut self, type_: MessageType, data: Value) -> Result<()> {
         match type_ {
             MessageType::ListPlugins => self.list_plugins(),
             MessageType::AddPlugin => self.add_plugin(data),
@@ -17,6 +17,29 @@ impl Server {
             MessageType::GetStyle => self.get_style(),
             MessageType::SetStyle => self.set_style(data),
             MessageType::RemoveStyle => self.remove_style(),
+            MessageType::Doctor => self.doctor(data),
+            MessageType::ListCatalog => self.list_catalog(data),
+            MessageType::SearchCatalog => self.search_catalog(data),
+            MessageType::InstallFromCatalog => self.install_from_catalog(data),
+            MessageType::CheckUpdates => self.check_updates(),
+            MessageType::UpgradePlugin => self.upgrade_plugin(data),
+            MessageType::RollbackPlugin => self.rollback_plugin(data),
+            MessageType::UploadFile => self.upload_file(data),
+            MessageType::GetSettings => self.get_settings(),
+            MessageType::SetSettings => self.set_settings(data),
+            MessageType::SavePreset => self.save_preset(data),
+            MessageType::ApplyPreset => self.apply_preset(data),
+            MessageType::ListPresets => self.list_presets(data),
+            MessageType::DeletePreset => self.delete_preset(data),
+            MessageType::SaveScene => self.save_scene(data),
+            MessageType::ApplyScene => self.apply_scene(data),
+            MessageType::ListScenes => self.list_scenes(),
+            MessageType::DeleteScene => self.delete_scene(data),
+            MessageType::AddInstance => self.add_instance(data),
+            MessageType::RenameInstance => self.rename_instance(data),
+            MessageType::RemoveInstance => self.remove_instance(data),
+            MessageType::ReorderPlugins => self.reorder_plugins(data),
+            MessageType::PluginFetch => self.plugin_fetch(data),
             _ => self.send(Message::error("Unsupported type.")),
         }
     }
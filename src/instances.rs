@@ -0,0 +1,396 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use crate::order::{append_to_order, remove_from_order};
+use crate::paths::{data_path, plugin_dir, PluginName};
+use crate::plugin::{apply_values, read_installed, Config, ConfigValue, PluginMeta};
+use crate::scenes::lock_switch;
+use crate::secrets::{decrypt, is_encrypted};
+use crate::upload::prune_uploads;
+use crate::validation::normalize;
+use crate::{Message, MessageType, Server};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use ws::Result;
+
+/// Separates the plugin name from the number of an instance in its id, as in
+/// `my-clock:2`.
+const ID_SEPARATOR: char = ':';
+/// The longest label of an instance, in characters.
+const MAX_LABEL_LENGTH: usize = 64;
+
+/// An additional instance of a plugin, with its own config values. The default instance
+/// of a plugin has the plugin name as id and keeps its values in `meta.json`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Instance {
+    pub(crate) id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+    pub(crate) configs: Vec<ConfigValue>,
+}
+
+/// Instances are kept outside the plugin directory, so they survive reinstalls.
+pub fn instances_path(plugin: &PluginName) -> Option<PathBuf> {
+    data_path(&format!("instances/{}.json", plugin))
+}
+
+pub fn read_instances(plugin: &PluginName) -> Vec<Instance> {
+    let raw = match instances_path(plugin).and_then(|p| fs::read_to_string(p).ok()) {
+        Some(r) => r,
+        None => return vec![],
+    };
+
+    serde_json::from_str(&raw).unwrap_or_else(|_| {
+        warn!("Failed to parse the instances of {}.", plugin);
+        vec![]
+    })
+}
+
+pub fn write_instances(
+    plugin: &PluginName,
+    instances: &[Instance],
+) -> std::result::Result<(), String> {
+    let path = instances_path(plugin).ok_or("Invalid instances path.")?;
+
+    if instances.is_empty() {
+        let _ = fs::remove_file(&path);
+        return Ok(());
+    }
+
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(&path, json!(instances).to_string()))
+        .map_err(|_| "Failed to write instances.".to_string())
+}
+
+/// Hands out the number of a new instance of a plugin. The last number of each plugin
+/// is kept in `data/instance-ids.json`, also after the plugin is removed, so a number is
+/// never reused and nothing still referring to a removed instance applies to a new one.
+fn next_instance_number(
+    plugin: &PluginName,
+    instances: &[Instance],
+) -> std::result::Result<u32, String> {
+    let path = data_path("instance-ids.json").ok_or("Invalid instance ids path.")?;
+    let mut last: BTreeMap<String, u32> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default();
+
+    // Instances restored from a scene may be newer than the saved number.
+    let highest = instances
+        .iter()
+        .filter_map(|i| i.id.rsplit_once(ID_SEPARATOR)?.1.parse::<u32>().ok())
+        .chain(last.get(plugin.as_str()).copied())
+        .max()
+        .unwrap_or(1);
+    let number = highest + 1;
+
+    last.insert(plugin.to_string(), number);
+    fs::write(path, json!(last).to_string()).map_err(|_| "Failed to write instance ids.")?;
+
+    Ok(number)
+}
+
+/// Removes the instances of a plugin, when it is removed.
+pub fn remove_instances(plugin: &PluginName) {
+    if let Some(path) = instances_path(plugin) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Returns the values saved for every instance of a plugin.
+pub fn instance_values(plugin: &PluginName) -> Vec<ConfigValue> {
+    read_instances(plugin)
+        .into_iter()
+        .flat_map(|i| i.configs)
+        .collect()
+}
+
+/// Removes the values of configs from every instance of a plugin, such as secrets that
+/// weren't carried over on reinstall.
+pub fn forget_instance_values(plugin: &PluginName, names: &[String]) {
+    let mut instances = read_instances(plugin);
+    let mut changed = false;
+    for instance in instances.iter_mut() {
+        let count = instance.configs.len();
+        instance.configs.retain(|cv| !names.contains(&cv.name));
+        changed |= instance.configs.len() != count;
+    }
+
+    if changed {
+        if let Err(e) = write_instances(plugin, &instances) {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Returns the id of an instance of a plugin, unless it refers to the default instance.
+pub fn instance_id<'a>(plugin: &PluginName, id: Option<&'a str>) -> Option<&'a str> {
+    id.filter(|id| *id != plugin.as_str())
+}
+
+/// Returns the configs of a plugin with the values of an instance. Values that no
+/// longer fit their config, such as after an upgrade, fall back to the default.
+pub fn instance_configs(meta: &PluginMeta, instance: &Instance) -> Vec<Config> {
+    let mut configs = meta.configs.clone().unwrap_or_default();
+
+    for config in configs.iter_mut() {
+        let value = instance
+            .configs
+            .iter()
+            .find(|cv| cv.name == config.name)
+            .map(|cv| &cv.value)
+            .filter(|v| match config.type_ == "secret" && is_encrypted(v) {
+                true => decrypt(v, &meta.name, &config.name).is_ok(),
+                false => normalize(&meta.name, config, v).is_ok(),
+            });
+
+        // Like in `meta.json`, a config inheriting a setting stays unset.
+        config.value = value
+            .cloned()
+            .or_else(|| config.inherit.is_none().then(|| config.default.clone()));
+    }
+
+    configs
+}
+
+/// Returns the meta of a plugin as it applies to an instance.
+pub fn instance_meta(meta: &PluginMeta, instance: &Instance) -> PluginMeta {
+    let mut instance_meta = meta.clone();
+    instance_meta.configs = Some(instance_configs(meta, instance));
+    instance_meta.id = Some(instance.id.clone());
+    instance_meta.label = instance.label.clone();
+
+    instance_meta
+}
+
+/// Returns every instance of a plugin as sent to clients, starting with the default
+/// instance.
+pub fn client_entries(meta: PluginMeta) -> Vec<Value> {
+    let mut entries: Vec<Value> = read_instances(&meta.name)
+        .iter()
+        .map(|i| instance_meta(&meta, i).into_client())
+        .collect();
+    entries.insert(0, meta.into_client());
+
+    entries
+}
+
+/// Returns the configs of an instance of an installed plugin, or of the plugin itself
+/// if no instance is given.
+pub fn find_configs(plugin: &PluginName, id: Option<&str>) -> Option<Vec<Config>> {
+    let meta = read_installed(plugin)?;
+
+    match instance_id(plugin, id) {
+        Some(id) => read_instances(plugin)
+            .iter()
+            .find(|i| i.id == id)
+            .map(|i| instance_configs(&meta, i)),
+        None => meta.configs,
+    }
+}
+
+/// Parses the label of an instance, if any.
+fn parse_label(data: &Value) -> std::result::Result<Option<String>, String> {
+    match data["label"].as_str().map(|l| l.trim()) {
+        Some(l) if l.chars().count() > MAX_LABEL_LENGTH => Err(format!(
+            "The label must be at most {} characters long.",
+            MAX_LABEL_LENGTH
+        )),
+        Some("") | None => Ok(None),
+        Some(l) => Ok(Some(l.to_string())),
+    }
+}
+
+impl Server {
+    /// Adds an instance of an installed plugin, with the default values of its configs.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the plugin and optionally a `label`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn add_instance(&self, data: Value) -> Result<()> {
+        let name = match data["name"].as_str().and_then(PluginName::parse) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get plugin.")),
+        };
+        let label = match parse_label(&data) {
+            Ok(l) => l,
+            Err(e) => return self.send(Message::error(&e)),
+        };
+
+        let _guard = lock_switch();
+        let meta = match read_installed(&name) {
+            Some(m) => m,
+            None => return self.send(Message::error("Plugin not found.")),
+        };
+
+        let mut instances = read_instances(&name);
+        let number = match next_instance_number(&name, &instances) {
+            Ok(n) => n,
+            Err(e) => return self.send(Message::error(&e)),
+        };
+
+        let instance = Instance {
+            id: format!("{}{}{}", name, ID_SEPARATOR, number),
+            label,
+            configs: vec![],
+        };
+        instances.push(instance.clone());
+
+        if let Err(e) = write_instances(&name, &instances) {
+            return self.send(Message::error(&e));
+        }
+        append_to_order(&instance.id);
+
+        self.broadcast(Message {
+            type_: MessageType::AddPlugin,
+            data: instance_meta(&meta, &instance).into_client(),
+        })
+    }
+
+    /// Sets config values of an instance, saves them and broadcasts the instance with a
+    /// `configPlugin` message.
+    pub(crate) fn update_instance_configs(
+        &self,
+        name: &PluginName,
+        id: &str,
+        configs: &[ConfigValue],
+    ) -> Result<()> {
+        let _guard = lock_switch();
+        let meta = match read_installed(name) {
+            Some(m) => m,
+            None => return self.send(Message::error("Plugin not found.")),
+        };
+
+        let mut instances = read_instances(name);
+        let instance = match instances.iter_mut().find(|i| i.id == id) {
+            Some(i) => i,
+            None => return self.send(Message::error("Instance not found.")),
+        };
+
+        let mut configs_with_values = instance_configs(&meta, instance);
+        if let Err(errors) = apply_values(name, &mut configs_with_values, configs) {
+            return self.send_validation_errors(name, errors);
+        }
+
+        instance.configs = configs_with_values
+            .into_iter()
+            .filter_map(|c| {
+                c.value.map(|value| ConfigValue {
+                    name: c.name,
+                    value,
+                })
+            })
+            .collect();
+        let instance = instance.clone();
+
+        if let Err(e) = write_instances(name, &instances) {
+            return self.send(Message::error(&e));
+        }
+        if let Some(dir) = plugin_dir(name) {
+            prune_uploads(&dir, name, meta.configs.as_deref().unwrap_or_default());
+        }
+
+        self.broadcast(Message {
+            type_: MessageType::ConfigPlugin,
+            data: instance_meta(&meta, &instance).into_client(),
+        })
+    }
+
+    /// Changes the label of an instance.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the plugin, the `id` of the instance
+    ///   and its new `label`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn rename_instance(&self, data: Value) -> Result<()> {
+        let (name, id) = match (
+            data["name"].as_str().and_then(PluginName::parse),
+            data["id"].as_str(),
+        ) {
+            (Some(n), Some(i)) => (n, i),
+            _ => return self.send(Message::error("Failed to get instance.")),
+        };
+        let label = match parse_label(&data) {
+            Ok(l) => l,
+            Err(e) => return self.send(Message::error(&e)),
+        };
+
+        let _guard = lock_switch();
+        let meta = match read_installed(&name) {
+            Some(m) => m,
+            None => return self.send(Message::error("Plugin not found.")),
+        };
+
+        let mut instances = read_instances(&name);
+        let instance = match instances.iter_mut().find(|i| i.id == id) {
+            Some(i) => i,
+            None => return self.send(Message::error("Instance not found.")),
+        };
+        instance.label = label;
+        let instance = instance.clone();
+
+        if let Err(e) = write_instances(&name, &instances) {
+            return self.send(Message::error(&e));
+        }
+
+        self.broadcast(Message {
+            type_: MessageType::ConfigPlugin,
+            data: instance_meta(&meta, &instance).into_client(),
+        })
+    }
+
+    /// Removes an instance of a plugin. The default instance is removed along with the
+    /// plugin.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the plugin and the `id` of the
+    ///   instance.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn remove_instance(&self, data: Value) -> Result<()> {
+        let (name, id) = match (
+            data["name"].as_str().and_then(PluginName::parse),
+            data["id"].as_str(),
+        ) {
+            (Some(n), Some(i)) => (n, i),
+            _ => return self.send(Message::error("Failed to get instance.")),
+        };
+
+        let _guard = lock_switch();
+        let mut instances = read_instances(&name);
+        let count = instances.len();
+        instances.retain(|i| i.id != id);
+
+        if instances.len() == count {
+            return self.send(Message::error("Instance not found."));
+        }
+
+        if let Err(e) = write_instances(&name, &instances) {
+            return self.send(Message::error(&e));
+        }
+        remove_from_order(&[id.to_string()]);
+        if let (Some(dir), Some(meta)) = (plugin_dir(&name), read_installed(&name)) {
+            prune_uploads(&dir, &name, meta.configs.as_deref().unwrap_or_default());
+        }
+
+        self.broadcast(Message {
+            type_: MessageType::RemovePlugin,
+            data: json!({
+                "name": name,
+                "id": id,
+            }),
+        })
+    }
+}
@@ -0,0 +1,144 @@
+use std::{fs, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+/// The hash algorithms accepted in integrity metadata, weakest first.
+const ALGORITHMS: [&str; 3] = ["sha256", "sha384", "sha512"];
+
+fn hash(algorithm: &str, bytes: &[u8]) -> Vec<u8> {
+    match algorithm {
+        "sha256" => Sha256::digest(bytes).to_vec(),
+        "sha384" => Sha384::digest(bytes).to_vec(),
+        _ => Sha512::digest(bytes).to_vec(),
+    }
+}
+
+/// Returns the integrity metadata of `bytes` in the format of subresource integrity,
+/// such as `sha384-oqVuAfXRKap7fdgcCY5uykM6+R9GqQ8K/uxy9rx7HNQlGYl1kPzQho1wx4JwY8wC`.
+pub fn digest(bytes: &[u8]) -> String {
+    format!("sha384-{}", STANDARD.encode(hash("sha384", bytes)))
+}
+
+/// Returns the integrity metadata of a file, or `None` if it can't be read.
+pub fn file_digest(path: &Path) -> Option<String> {
+    fs::read(path).ok().map(|bytes| digest(&bytes))
+}
+
+/// Returns the integrity metadata of every file in a directory and its subdirectories,
+/// by path relative to the directory. Symbolic links aren't followed.
+pub fn dir_digests(dir: &Path) -> Vec<(String, String)> {
+    let mut digests = Vec::new();
+    collect_digests(dir, "", &mut digests);
+
+    digests
+}
+
+fn collect_digests(dir: &Path, prefix: &str, digests: &mut Vec<(String, String)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let relative = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => {
+                collect_digests(&entry.path(), &format!("{}/", relative), digests)
+            }
+            Ok(t) if t.is_file() => {
+                if let Some(digest) = file_digest(&entry.path()) {
+                    digests.push((relative, digest));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Checks `bytes` against integrity metadata in the format of subresource integrity.
+/// As in browsers, the metadata may list several hashes, only the ones of the strongest
+/// algorithm are used and any of them may match.
+///
+/// # Parameters
+///
+/// * `integrity` - The expected integrity metadata.
+/// * `bytes` - The downloaded bytes.
+/// * `name` - The name of the file, used in error messages.
+pub fn check(integrity: &str, bytes: &[u8], name: &str) -> Result<(), String> {
+    let hashes: Vec<(usize, &str)> = integrity
+        .split_whitespace()
+        .filter_map(|token| {
+            // Options after `?` are reserved and ignored.
+            let token = token.split('?').next()?;
+            let (algorithm, hash) = token.split_once('-')?;
+            let strength = ALGORITHMS.iter().position(|a| *a == algorithm)?;
+
+            Some((strength, hash))
+        })
+        .collect();
+
+    let strongest = hashes
+        .iter()
+        .map(|(strength, _)| *strength)
+        .max()
+        .ok_or_else(|| format!("Unsupported integrity metadata for {}.", name))?;
+
+    let actual = STANDARD.encode(hash(ALGORITHMS[strongest], bytes));
+    let matched = hashes
+        .iter()
+        .any(|(strength, hash)| *strength == strongest && *hash == actual);
+
+    match matched {
+        true => Ok(()),
+        false => Err(format!("{} does not match its integrity hash.", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(algorithm: &str, bytes: &[u8]) -> String {
+        format!("{}-{}", algorithm, STANDARD.encode(hash(algorithm, bytes)))
+    }
+
+    #[test]
+    fn accepts_matching_hashes() {
+        let bytes = b"console.log(1);";
+
+        assert!(check(&digest(bytes), bytes, "a.js").is_ok());
+        for algorithm in ALGORITHMS {
+            assert!(check(&encoded(algorithm, bytes), bytes, "a.js").is_ok());
+        }
+        // Options are ignored, and any hash of the strongest algorithm may match.
+        let integrity = format!("sha384-abc {}?foo", encoded("sha384", bytes));
+        assert!(check(&integrity, bytes, "a.js").is_ok());
+    }
+
+    #[test]
+    fn rejects_mismatching_hashes() {
+        let bytes = b"console.log(1);";
+
+        assert!(check(&digest(b"console.log(2);"), bytes, "a.js").is_err());
+        assert!(check(&encoded("sha256", bytes).to_uppercase(), bytes, "a.js").is_err());
+    }
+
+    #[test]
+    fn only_uses_the_strongest_algorithm() {
+        let bytes = b"console.log(1);";
+
+        let weak_matches = format!("{} {}", encoded("sha256", bytes), digest(b"other"));
+        assert!(check(&weak_matches, bytes, "a.js").is_err());
+
+        let strong_matches = format!("sha256-wrong {}", encoded("sha512", bytes));
+        assert!(check(&strong_matches, bytes, "a.js").is_ok());
+    }
+
+    #[test]
+    fn rejects_unsupported_metadata() {
+        for integrity in ["", "md5-abc", "sha384", "sha1-abc"] {
+            assert!(check(integrity, b"", "a.js").is_err(), "{}", integrity);
+        }
+    }
+}
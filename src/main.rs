@@ -1,22 +1,44 @@
+#![allow(clippy::result_large_err)]
+
+mod catalog;
+mod compress;
+mod doctor;
+mod fetch;
 mod handler;
+mod instances;
+mod integrity;
 mod logger;
+mod order;
+mod package;
+mod paths;
 mod plugin;
+mod presets;
+mod proxy;
+mod scenes;
+mod schema;
+mod secrets;
+mod serve;
+mod settings;
 mod style;
+mod trust;
+mod updates;
+mod upload;
+mod validation;
+mod vendor;
 
-use std::{
-    fs,
-    path::{Path, PathBuf},
-};
+use std::fs;
 
 use log::{error, info};
-use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use ws::{
-    listen, CloseCode, Handler, Handshake, Message as WSMessage, Request, Response, Result, Sender,
+    CloseCode, Handler, Handshake, Message as WSMessage, Request, Response, Result, Sender,
+    WebSocket,
 };
 
 use logger::setup_logger;
+use paths::DATA_DIR;
+use serve::{read_html, try_find_plugin_or_static};
 
 fn _data_default() -> Value {
     Value::Null
@@ -42,6 +64,32 @@ enum MessageType {
     SetStyle,
     RemoveStyle,
     GetStyle,
+    Doctor,
+    ListCatalog,
+    SearchCatalog,
+    InstallFromCatalog,
+    CheckUpdates,
+    PluginUpdates,
+    UpgradePlugin,
+    RollbackPlugin,
+    PluginMigration,
+    ValidationError,
+    UploadFile,
+    GetSettings,
+    SetSettings,
+    SavePreset,
+    ApplyPreset,
+    ListPresets,
+    DeletePreset,
+    SaveScene,
+    ApplyScene,
+    ListScenes,
+    DeleteScene,
+    AddInstance,
+    RenameInstance,
+    RemoveInstance,
+    ReorderPlugins,
+    PluginFetch,
     #[serde(untagged)]
     Unknown(String),
 }
@@ -58,11 +106,16 @@ impl Message {
 struct Server {
     out: Sender,
     ip_addr: Option<String>,
+    upload: Option<upload::PendingUpload>,
 }
 
 impl Server {
     fn new(out: Sender) -> Self {
-        Server { out, ip_addr: None }
+        Server {
+            out,
+            ip_addr: None,
+            upload: None,
+        }
     }
 
     fn send(&self, msg: Message) -> Result<()> {
@@ -76,115 +129,21 @@ impl Server {
     }
 }
 
-fn read_file_in_folder(folder: &str, filename: &str) -> Option<String> {
-    let folder_path = Path::new(folder);
-
-    if !folder_path.is_dir() {
-        return None;
-    }
-
-    let file_path: PathBuf = folder_path.join(filename);
-
-    if file_path.is_file() {
-        match fs::read_to_string(&file_path) {
-            Ok(contents) => Some(contents),
-            Err(_) => None,
-        }
-    } else {
-        None
-    }
-}
-
-fn try_serve_static_file(folder: &str, filename: &str) -> Option<Response> {
-    if let Some(content) = read_file_in_folder(folder, filename) {
-        let extension = Path::new(filename)
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("");
-
-        let content_type = match extension {
-            "js" => b"text/javascript".to_vec(),
-            "css" => b"text/css".to_vec(),
-            _ => b"text/plain".to_vec(),
-        };
-
-        let mut response = Response::new(200, "OK", content.as_bytes().to_vec());
-        response
-            .headers_mut()
-            .push(("Content-Type".into(), content_type));
-        response
-            .headers_mut()
-            .push(("Access-Control-Allow-Origin".into(), "*".into()));
-
-        return Some(response);
-    }
-    None
-}
-
-fn try_find_plugin_or_static(path: &str) -> Result<Response> {
-    let not_found = Ok(Response::new(404, "Not Found", b"404 - Not Found".to_vec()));
-
-    let style_re = Regex::new("custom/style.css").unwrap();
-    if style_re.captures(path).is_some() {
-        if let Some(response) = try_serve_static_file("data", "style.css") {
-            return Ok(response);
-        }
-    }
-
-    let plugin_re = Regex::new("/plugin/(.*)/(.*\\.js)").unwrap();
-    if let Some(caps) = plugin_re.captures(path) {
-        if let Some(response) =
-            try_serve_static_file(format!("data/plugins/{}", &caps[1]).as_str(), &caps[2])
-        {
-            return Ok(response);
-        }
-    }
-
-    let edit_re = Regex::new(r"/edit/(.*)").unwrap();
-    if let Some(caps) = edit_re.captures(path) {
-        if let Some(response) = try_serve_static_file("static/editor", &caps[1]) {
-            return Ok(response);
-        }
-    }
-
-    let display_re = Regex::new(r"/(.*)").unwrap();
-    if let Some(caps) = display_re.captures(path) {
-        if let Some(response) = try_serve_static_file("static/display", &caps[1]) {
-            return Ok(response);
-        }
-    }
-
-    not_found
-}
-
-fn read_html(folder: &str) -> Response {
-    let mut resp = Response::new(
-        200,
-        "OK",
-        read_file_in_folder(folder, "index.html")
-            .unwrap()
-            .as_bytes()
-            .to_vec(),
-    );
-
-    resp.headers_mut()
-        .push(("Content-Type".into(), b"text/html".to_vec()));
-
-    return resp;
-}
-
 impl Handler for Server {
     fn on_request(&mut self, req: &Request) -> Result<Response> {
-        match req.resource() {
+        let path = req.resource().split(['?', '#']).next().unwrap_or("/");
+
+        match path {
             "/" => {
                 if req.header("upgrade").is_some() {
                     Response::from_request(req)
                 } else {
-                    Ok(read_html("static/display"))
+                    Ok(read_html(req, "display"))
                 }
             }
-            "/edit" => Ok(read_html("static/editor")),
-            _ => try_find_plugin_or_static(req.resource()),
+            "/edit" => Ok(read_html(req, "editor")),
+            _ if path.starts_with("/scenes/") => self.trigger_scene(req, &path["/scenes/".len()..]),
+            _ => try_find_plugin_or_static(req, path),
         }
     }
 
@@ -206,7 +165,7 @@ impl Handler for Server {
     fn on_message(&mut self, mesg: WSMessage) -> Result<()> {
         let text = match mesg {
             WSMessage::Text(t) => t,
-            _ => return self.send(Message::error("Binary format is not supported.")),
+            WSMessage::Binary(chunk) => return self.receive_chunk(&chunk),
         };
 
         let json = match serde_json::from_str::<Message>(&text) {
@@ -221,7 +180,25 @@ impl Handler for Server {
 fn main() {
     setup_logger().expect("Failed to initialize logger");
 
-    if let Err(error) = listen("0.0.0.0:3012", |out| Server::new(out)) {
+    if let Err(error) = fs::create_dir_all(DATA_DIR) {
+        error!("Failed to create the data directory due to {:?}", error);
+        return;
+    }
+
+    doctor::startup_check();
+
+    let socket = match WebSocket::new(Server::new) {
+        Ok(s) => s,
+        Err(error) => {
+            error!("Failed to create WebSocket due to {:?}", error);
+            return;
+        }
+    };
+
+    updates::spawn_checker(socket.broadcaster());
+    scenes::spawn_scheduler(socket.broadcaster());
+
+    if let Err(error) = socket.listen("0.0.0.0:3012") {
         error!("Failed to create WebSocket due to {:?}", error);
     }
 }
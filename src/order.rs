@@ -0,0 +1,138 @@
+use std::fs;
+
+use crate::instances::read_instances;
+use crate::paths::{data_path, PluginName};
+use crate::plugin::installed_plugins;
+use crate::{Message, MessageType, Server};
+
+use log::warn;
+use serde_json::{from_value, json, Value};
+use ws::Result;
+
+/// Returns the ids of the plugins and instances in the order set by the user, saved in
+/// `data/order.json`.
+fn read_order() -> Vec<String> {
+    let raw = match data_path("order.json").and_then(|p| fs::read_to_string(p).ok()) {
+        Some(r) => r,
+        None => return vec![],
+    };
+
+    serde_json::from_str(&raw).unwrap_or_else(|_| {
+        warn!("Failed to parse order.json.");
+        vec![]
+    })
+}
+
+fn write_order(order: &[String]) -> std::result::Result<(), String> {
+    let path = data_path("order.json").ok_or("Invalid order path.")?;
+
+    fs::write(path, json!(order).to_string()).map_err(|_| "Failed to write order.".to_string())
+}
+
+/// Returns the position of a plugin or instance, if it has one.
+pub fn order_position(id: &str) -> Option<usize> {
+    read_order().iter().position(|i| i == id)
+}
+
+/// Puts a newly installed plugin or added instance at the end of the order. The order
+/// starts from every plugin and instance already shown, even if it was never saved, so
+/// the new one comes after them.
+pub fn append_to_order(id: &str) {
+    if read_order().iter().any(|i| i == id) {
+        return;
+    }
+
+    let mut order = current_order();
+    order.retain(|i| i != id);
+    order.push(id.to_string());
+    if let Err(e) = write_order(&order) {
+        warn!("{}", e);
+    }
+}
+
+/// Removes plugins or instances from the order, when they are removed.
+pub fn remove_from_order(ids: &[String]) {
+    let mut order = read_order();
+    let count = order.len();
+    order.retain(|i| !ids.contains(i));
+
+    if order.len() != count {
+        if let Err(e) = write_order(&order) {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Returns the ids of a plugin and all its instances.
+pub fn plugin_ids(plugin: &PluginName) -> Vec<String> {
+    let mut ids = vec![plugin.to_string()];
+    ids.extend(read_instances(plugin).into_iter().map(|i| i.id));
+
+    ids
+}
+
+/// Returns the ids of every plugin and instance in order. Those without a position,
+/// such as plugins installed before the order was kept, come last, sorted by id.
+fn current_order() -> Vec<String> {
+    let mut unordered: Vec<String> = installed_plugins()
+        .iter()
+        .flat_map(|meta| plugin_ids(&meta.name))
+        .collect();
+
+    let mut order: Vec<String> = read_order()
+        .into_iter()
+        .filter(|id| unordered.contains(id))
+        .collect();
+    unordered.retain(|id| !order.contains(id));
+    unordered.sort();
+    order.extend(unordered);
+
+    order
+}
+
+impl Server {
+    /// Changes the order plugins are listed and shown in, and broadcasts the new order
+    /// with a `reorderPlugins` message.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with `order`, an array of the ids of plugins and
+    ///   instances. Those left out keep their relative order after the given ones.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn reorder_plugins(&self, data: Value) -> Result<()> {
+        let given = match from_value::<Vec<String>>(data["order"].clone()) {
+            Ok(o) => o,
+            Err(_) => return self.send(Message::error("Failed to parse order.")),
+        };
+
+        let current = current_order();
+        if let Some(id) = given.iter().find(|id| !current.contains(id)) {
+            return self.send(Message::error(&format!("Plugin {} not found.", id)));
+        }
+        if given
+            .iter()
+            .enumerate()
+            .any(|(i, id)| given[..i].contains(id))
+        {
+            return self.send(Message::error("The order lists a plugin twice."));
+        }
+
+        let rest: Vec<String> = current
+            .into_iter()
+            .filter(|id| !given.contains(id))
+            .collect();
+        let order: Vec<String> = given.into_iter().chain(rest).collect();
+
+        if let Err(e) = write_order(&order) {
+            return self.send(Message::error(&e));
+        }
+
+        self.broadcast(Message {
+            type_: MessageType::ReorderPlugins,
+            data: json!({ "order": order }),
+        })
+    }
+}
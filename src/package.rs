@@ -0,0 +1,237 @@
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::paths::confine;
+
+/// The largest package accepted, compressed.
+pub const MAX_PACKAGE_SIZE: u64 = 16 * 1024 * 1024;
+/// The largest total size of the files in a package.
+const MAX_EXTRACTED_SIZE: u64 = 64 * 1024 * 1024;
+/// The largest number of files in a package.
+const MAX_ENTRIES: usize = 2048;
+
+/// Files read from a package, with their paths relative to the package root.
+type Entries = Vec<(PathBuf, Vec<u8>)>;
+
+/// Only accepts relative paths without `..`, so an entry can't be written outside of
+/// the plugin directory.
+fn entry_path(raw: &Path) -> Result<PathBuf, String> {
+    let mut path = PathBuf::new();
+
+    for component in raw.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => continue,
+            _ => return Err(format!("Invalid path in package: {}.", raw.display())),
+        }
+    }
+
+    Ok(path)
+}
+
+/// Reads an entry while keeping track of the total extracted size.
+fn read_entry(reader: impl Read, total: &mut u64) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    reader
+        .take(MAX_EXTRACTED_SIZE - *total + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|_| "Failed to read package.".to_string())?;
+
+    *total += bytes.len() as u64;
+    if *total > MAX_EXTRACTED_SIZE {
+        return Err(format!(
+            "Package is larger than {} bytes when extracted.",
+            MAX_EXTRACTED_SIZE
+        ));
+    }
+
+    Ok(bytes)
+}
+
+fn read_zip(bytes: &[u8]) -> Result<Entries, String> {
+    let mut archive =
+        ZipArchive::new(Cursor::new(bytes)).map_err(|_| "Failed to open zip package.")?;
+    if archive.len() > MAX_ENTRIES {
+        return Err(format!("Package has more than {} files.", MAX_ENTRIES));
+    }
+
+    let mut entries = Vec::new();
+    let mut total = 0;
+    for i in 0..archive.len() {
+        let file = archive
+            .by_index(i)
+            .map_err(|_| "Failed to read zip package.")?;
+
+        if file.is_dir() {
+            continue;
+        }
+        if file.is_symlink() {
+            return Err(format!(
+                "Links are not allowed in package: {}.",
+                file.name()
+            ));
+        }
+
+        let path = entry_path(Path::new(file.name()))?;
+        entries.push((path, read_entry(file, &mut total)?));
+    }
+
+    Ok(entries)
+}
+
+fn read_tar_gz(bytes: &[u8]) -> Result<Entries, String> {
+    let mut archive = tar::Archive::new(GzDecoder::new(bytes));
+    let iter = archive
+        .entries()
+        .map_err(|_| "Failed to open tar.gz package.")?;
+
+    let mut entries = Vec::new();
+    let mut total = 0;
+    for entry in iter {
+        let entry = entry.map_err(|_| "Failed to read tar.gz package.")?;
+        let raw = entry
+            .path()
+            .map_err(|_| "Failed to read tar.gz package.")?
+            .to_path_buf();
+
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => continue,
+            tar::EntryType::Regular | tar::EntryType::Continuous => {}
+            // Extended headers are handled by the tar crate itself.
+            tar::EntryType::XGlobalHeader | tar::EntryType::XHeader => continue,
+            _ => {
+                return Err(format!(
+                    "Only regular files are allowed in package: {}.",
+                    raw.display()
+                ))
+            }
+        }
+
+        if entries.len() >= MAX_ENTRIES {
+            return Err(format!("Package has more than {} files.", MAX_ENTRIES));
+        }
+
+        let path = entry_path(&raw)?;
+        entries.push((path, read_entry(entry, &mut total)?));
+    }
+
+    Ok(entries)
+}
+
+/// Strips the top-level directory if every file is inside it, as in archives of a
+/// repository or folder.
+fn strip_common_root(entries: Entries) -> Entries {
+    let root = match entries.first().and_then(|(p, _)| p.components().next()) {
+        Some(Component::Normal(root)) => PathBuf::from(root),
+        _ => return entries,
+    };
+
+    let nested = entries
+        .iter()
+        .all(|(p, _)| p.starts_with(&root) && p.components().count() > 1);
+    if !nested {
+        return entries;
+    }
+
+    entries
+        .into_iter()
+        .map(|(p, bytes)| (p.strip_prefix(&root).unwrap().to_path_buf(), bytes))
+        .collect()
+}
+
+/// Extracts a zip or tar.gz plugin package into `dest`. The package must contain a
+/// `meta.json`, either at its root or inside a single top-level directory.
+pub fn unpack(bytes: &[u8], dest: &Path) -> Result<(), String> {
+    if bytes.len() as u64 > MAX_PACKAGE_SIZE {
+        return Err(format!(
+            "Package is larger than {} bytes.",
+            MAX_PACKAGE_SIZE
+        ));
+    }
+
+    let entries = if bytes.starts_with(b"PK\x03\x04") {
+        read_zip(bytes)?
+    } else if bytes.starts_with(&[0x1f, 0x8b]) {
+        read_tar_gz(bytes)?
+    } else {
+        return Err("Unsupported package format, expected zip or tar.gz.".to_string());
+    };
+
+    let entries = strip_common_root(entries);
+    if !entries.iter().any(|(p, _)| p == Path::new("meta.json")) {
+        return Err("Package does not contain a meta.json.".to_string());
+    }
+
+    for (path, bytes) in entries {
+        let target = confine(dest, &path)
+            .ok_or_else(|| format!("Invalid path in package: {}.", path.display()))?;
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|_| "Failed to extract package.")?;
+        }
+        fs::write(&target, bytes).map_err(|_| "Failed to extract package.")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(paths: &[&str]) -> Entries {
+        paths.iter().map(|p| (PathBuf::from(p), vec![])).collect()
+    }
+
+    fn paths(entries: &Entries) -> Vec<&Path> {
+        entries.iter().map(|(p, _)| p.as_path()).collect()
+    }
+
+    #[test]
+    fn accepts_relative_entry_paths() {
+        assert_eq!(
+            entry_path(Path::new("assets/icon.png")),
+            Ok(PathBuf::from("assets/icon.png"))
+        );
+        assert_eq!(
+            entry_path(Path::new("./meta.json")),
+            Ok(PathBuf::from("meta.json"))
+        );
+    }
+
+    #[test]
+    fn rejects_escaping_entry_paths() {
+        for path in ["../meta.json", "assets/../../x", "/etc/passwd"] {
+            assert!(entry_path(Path::new(path)).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn strips_a_single_top_level_directory() {
+        let stripped = strip_common_root(entries(&["plugin/meta.json", "plugin/assets/a.png"]));
+
+        assert_eq!(
+            paths(&stripped),
+            [Path::new("meta.json"), Path::new("assets/a.png")]
+        );
+    }
+
+    #[test]
+    fn keeps_entries_without_a_common_root() {
+        let flat = entries(&["meta.json", "index.js"]);
+        assert_eq!(paths(&strip_common_root(flat.clone())), paths(&flat));
+
+        let mixed = entries(&["plugin/meta.json", "other/index.js"]);
+        assert_eq!(paths(&strip_common_root(mixed.clone())), paths(&mixed));
+
+        // A file named like the directory isn't nested in it.
+        let shallow = entries(&["plugin/meta.json", "plugin"]);
+        assert_eq!(paths(&strip_common_root(shallow.clone())), paths(&shallow));
+    }
+}
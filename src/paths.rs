@@ -0,0 +1,234 @@
+use std::{
+    fmt, fs,
+    path::{Component, Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const DATA_DIR: &str = "data";
+
+/// Names reserved by the custom element specification.
+const RESERVED_NAMES: [&str; 8] = [
+    "annotation-xml",
+    "color-profile",
+    "font-face",
+    "font-face-src",
+    "font-face-uri",
+    "font-face-format",
+    "font-face-name",
+    "missing-glyph",
+];
+
+/// A validated plugin name.
+///
+/// The name doubles as the plugin directory and the custom element tag used by the
+/// display, so it must be a valid custom element name: it starts with a lowercase
+/// letter, contains a hyphen and only consists of lowercase letters, digits, `-`, `.`
+/// and `_`. This also rules out path separators.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PluginName(String);
+
+impl PluginName {
+    pub fn parse(name: &str) -> Option<Self> {
+        let mut chars = name.chars();
+
+        let valid = name.len() <= 128
+            && chars.next().is_some_and(|c| c.is_ascii_lowercase())
+            && chars.all(|c| matches!(c, 'a'..='z' | '0'..='9' | '-' | '.' | '_'))
+            && name.contains('-')
+            && !RESERVED_NAMES.contains(&name);
+
+        valid.then(|| PluginName(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for PluginName {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        PluginName::parse(&value).ok_or_else(|| format!("invalid plugin name \"{}\"", value))
+    }
+}
+
+impl From<PluginName> for String {
+    fn from(value: PluginName) -> Self {
+        value.0
+    }
+}
+
+impl fmt::Display for PluginName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Joins `relative` onto `root` and makes sure the canonicalized result stays inside
+/// `root`. The path does not need to exist, in which case its deepest existing
+/// ancestor is canonicalized instead.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The resolved path, or `None` if it escapes `root`.
+pub fn confine(root: &Path, relative: &Path) -> Option<PathBuf> {
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return None;
+    }
+
+    let root = root.canonicalize().ok()?;
+    let joined = root.join(relative);
+
+    let mut existing = joined.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        rest.push(existing.file_name()?);
+        existing = existing.parent()?;
+    }
+
+    let mut resolved = existing.canonicalize().ok()?;
+    for part in rest.iter().rev() {
+        resolved.push(part);
+    }
+
+    resolved.starts_with(&root).then_some(resolved)
+}
+
+/// Resolves a path inside the data directory.
+pub fn data_path(relative: &str) -> Option<PathBuf> {
+    confine(Path::new(DATA_DIR), Path::new(relative))
+}
+
+/// Resolves the directory of a plugin.
+pub fn plugin_dir(name: &PluginName) -> Option<PathBuf> {
+    data_path(&format!("plugins/{}", name))
+}
+
+/// Resolves the directory the previously installed version of a plugin is kept in.
+pub fn backup_dir(name: &PluginName) -> Option<PathBuf> {
+    data_path(&format!("backups/{}", name))
+}
+
+/// A temporary directory inside the data directory, removed when dropped. Plugins are
+/// assembled in one and then moved into place, so a failed install leaves nothing
+/// behind.
+pub struct StagingDir(PathBuf);
+
+impl StagingDir {
+    pub fn new() -> Option<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+
+        let path = data_path(&format!("staging/{}-{}", process::id(), nanos))?;
+        fs::create_dir_all(&path).ok()?;
+
+        Some(StagingDir(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty directory to confine paths to.
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("widgetbox-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("plugins")).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn parses_valid_names() {
+        for name in ["my-clock", "a-b", "weather-2.0", "x_y-z"] {
+            assert!(PluginName::parse(name).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        let too_long = format!("a-{}", "b".repeat(127));
+        for name in [
+            "",
+            "clock",
+            "My-clock",
+            "1-clock",
+            "-clock",
+            "my clock",
+            "my-clock/..",
+            "../my-clock",
+            "my-clock\\x",
+            "..",
+            too_long.as_str(),
+        ] {
+            assert!(PluginName::parse(name).is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_names() {
+        for name in RESERVED_NAMES {
+            assert!(PluginName::parse(name).is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn confines_relative_paths() {
+        let root = temp_root("confine");
+        let canonical = root.canonicalize().unwrap();
+
+        assert_eq!(
+            confine(&root, Path::new("plugins/my-clock/meta.json")),
+            Some(canonical.join("plugins/my-clock/meta.json"))
+        );
+        assert_eq!(
+            confine(&root, Path::new("./plugins")),
+            Some(canonical.join("plugins"))
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn rejects_escaping_paths() {
+        let root = temp_root("escape");
+
+        for path in ["..", "../x", "plugins/../../x", "plugins/..", "/etc/passwd"] {
+            assert_eq!(confine(&root, Path::new(path)), None, "{}", path);
+        }
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_out_of_the_root() {
+        let root = temp_root("symlink");
+        std::os::unix::fs::symlink(std::env::temp_dir(), root.join("plugins/link")).unwrap();
+
+        assert_eq!(confine(&root, Path::new("plugins/link/x")), None);
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -1,12 +1,29 @@
+use crate::compress::precompress_dir;
+use crate::fetch::{fetch_bytes, fetch_text, parse_location, resolve_url, MAX_SCRIPT_SIZE};
+use crate::instances::{client_entries, forget_instance_values, instance_id, remove_instances};
+use crate::integrity::{check, dir_digests};
+use crate::order::{append_to_order, order_position, plugin_ids, remove_from_order};
+use crate::package::{unpack, MAX_PACKAGE_SIZE};
+use crate::paths::{backup_dir, data_path, plugin_dir, PluginName, StagingDir};
+use crate::presets::{forget_preset_values, remove_presets};
+use crate::scenes::{forget_scene_values, lock_switch};
+use crate::secrets::{decrypt, encrypt, is_encrypted, mask_secrets, MASK};
+use crate::serve::{content_hash, REVISION_LENGTH};
+use crate::settings::Settings;
+use crate::trust::{manifest, verify, Trust, TrustPolicy, TrustStatus, TrustStore};
+use crate::upload::{copy_uploads, prune_uploads, UPLOAD_DIR};
+use crate::validation::{normalize, normalize_defaults, validate_declarations, FieldError};
+use crate::vendor::vendor_modules;
 use crate::{Message, MessageType, Server};
 
-use reqwest::blocking::get;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::warn;
 use serde::{Deserialize, Serialize};
 use serde_json::{from_value, json, to_value, Value};
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::Write;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
 use std::path::Path;
+use url::Url;
 use ws::Result;
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -16,257 +33,784 @@ pub struct SelectOption {
     value: Option<Value>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+impl SelectOption {
+    /// Returns the value of the option, which defaults to its name.
+    pub fn value(&self) -> Value {
+        self.value
+            .clone()
+            .unwrap_or_else(|| Value::String(self.name.clone()))
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Config {
-    name: String,
+    pub(crate) name: String,
     #[serde(rename = "type")]
-    type_: String,
-    default: Value,
-    value: Option<Value>,
+    pub(crate) type_: String,
+    pub(crate) default: Value,
+    pub(crate) value: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     hint: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    options: Option<Vec<SelectOption>>,
+    pub(crate) options: Option<Vec<SelectOption>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) step: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) required: bool,
+    /// The types of files an `upload` config accepts, such as `image/*`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) accept: Option<Vec<String>>,
+    /// The largest file in bytes an `upload` config accepts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_size: Option<u64>,
+    /// The custom element a `webComponent` config is edited with, defined by the
+    /// plugin's editor script.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) element: Option<String>,
+    /// The hosts the value of a `secret` config may be sent to by `pluginFetch`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) hosts: Option<Vec<String>>,
+    /// The JSON Schema the value of an `object` config must match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) schema: Option<Value>,
+    /// The global setting the config takes its value from, unless it is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) inherit: Option<String>,
+    /// Whether the value sent to clients is the inherited setting.
+    #[serde(skip_deserializing, skip_serializing_if = "std::ops::Not::not")]
+    inherited: bool,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ConfigValue {
-    name: String,
-    value: Value,
+    pub(crate) name: String,
+    pub(crate) value: Value,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Script {
     #[serde(skip_serializing_if = "Option::is_none")]
     url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     inline: Option<String>,
+    /// The expected hash of the script in the format of subresource integrity.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    integrity: Option<String>,
+}
+
+impl Script {
+    /// Returns the script source, either the inline contents or the body fetched
+    /// from `url`, after checking it against `integrity` if given.
+    pub fn fetch(&self) -> std::result::Result<String, String> {
+        let source = match (&self.inline, &self.url) {
+            (Some(inline), _) => inline.clone(),
+            (None, Some(url)) => fetch_text(url)?,
+            (None, None) => {
+                return Err("The script has neither a URL nor inline source.".to_string())
+            }
+        };
+
+        self.check(source.as_bytes())?;
+        Ok(source)
+    }
+
+    /// Checks the script against `integrity`, if given.
+    fn check(&self, bytes: &[u8]) -> std::result::Result<(), String> {
+        match &self.integrity {
+            Some(integrity) => check(
+                integrity,
+                bytes,
+                self.url.as_deref().unwrap_or("The inline script"),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolves a relative `url` against the location of the meta it was declared in.
+    pub fn resolve(&mut self, base: Option<&Url>) -> std::result::Result<(), String> {
+        if let (None, Some(url)) = (&self.inline, &self.url) {
+            self.url = Some(resolve_url(base, url)?.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the URL the script is downloaded from, unless it is inline.
+    pub fn source_url(&self) -> Option<Url> {
+        match self.inline {
+            Some(_) => None,
+            None => Url::parse(self.url.as_ref()?).ok(),
+        }
+    }
+}
+
+/// Where an installed plugin was installed from, used to check for updates.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InstallSource {
+    Meta(String),
+    Package(String),
+}
+
+impl InstallSource {
+    /// Returns the data of an `addPlugin` message installing from this source.
+    pub fn install_data(&self) -> Value {
+        match self {
+            InstallSource::Meta(url) => json!({ "url": url }),
+            InstallSource::Package(url) => json!({ "package": { "url": url } }),
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PluginMeta {
-    name: String,
-    version: String,
+    pub(crate) name: PluginName,
+    pub(crate) version: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    configs: Option<Vec<Config>>,
+    pub(crate) configs: Option<Vec<Config>>,
     #[serde(skip_serializing_if = "Option::is_none", rename = "backgroundScript")]
-    background_script: Option<Script>,
-    script: Script,
+    pub(crate) background_script: Option<Script>,
+    /// Defines the custom elements the editor renders for `webComponent` configs.
+    #[serde(skip_serializing_if = "Option::is_none", rename = "editorScript")]
+    pub(crate) editor_script: Option<Script>,
+    #[serde(default)]
+    pub(crate) script: Script,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trust: Option<Trust>,
+    /// The hashes of the files as saved to the plugin directory, such as the scripts,
+    /// vendored modules and unpacked files, used to detect files modified after
+    /// installation.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) integrity: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<InstallSource>,
+    /// The instance the meta is sent to clients for, the plugin name for the default
+    /// instance.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<String>,
+    /// The label of the instance.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+    /// The position of the instance in the order set by the user.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    order: Option<usize>,
 }
 
 impl PluginMeta {
-    pub fn update_script(&mut self) {
-        self.script.url = Some(format!("/plugin/{}/index.js", self.name));
+    /// Returns the URL of a file in the plugin directory, with a revision derived from
+    /// its content so that a reinstalled script never hits a stale cache.
+    fn versioned_url(&self, filename: &str) -> String {
+        let url = format!("/plugin/{}/{}", self.name, filename);
+
+        match plugin_dir(&self.name).and_then(|d| content_hash(&d.join(filename))) {
+            Some(hash) => format!("{}?v={}", url, &hash[..REVISION_LENGTH]),
+            None => url,
+        }
+    }
+
+    /// Returns the meta as sent to clients, with versioned script URLs, the values of
+    /// secret configs masked, unset configs resolved from the global settings and its
+    /// position in the order.
+    pub fn into_client(mut self) -> Value {
+        self.update_script();
+        mask_secrets(self.configs.iter_mut().flatten());
+        let id = self.id.get_or_insert_with(|| self.name.to_string());
+        self.order = order_position(id);
+
+        let settings = Settings::load();
+        for config in self.configs.iter_mut().flatten() {
+            let is_unset = config.value.as_ref().is_none_or(|v| v.is_null() || v == "");
+
+            if let (true, Some(setting)) = (is_unset, config.inherit.as_deref()) {
+                config.value = match settings.get(setting) {
+                    Some(value) => {
+                        config.inherited = true;
+                        Some(value)
+                    }
+                    None => Some(config.default.clone()),
+                };
+            }
+        }
+
+        to_value(self).unwrap()
+    }
+
+    fn update_script(&mut self) {
+        self.script.url = Some(self.versioned_url("index.js"));
         self.script.inline = None;
+        // The served script may have had its imports rewritten.
+        self.script.integrity = None;
 
         if self.background_script.is_some() {
             self.background_script = Some(Script {
-                url: Some(format!("/plugin/{}/background.js", self.name)),
+                url: Some(self.versioned_url("background.js")),
                 inline: None,
+                integrity: None,
             })
         }
+
+        if self.editor_script.is_some() {
+            self.editor_script = Some(Script {
+                url: Some(self.versioned_url("editor.js")),
+                inline: None,
+                integrity: None,
+            })
+        }
+    }
+
+    /// Records the hashes of every file served from the plugin directory.
+    pub(crate) fn record_integrity(&mut self, dir: &Path) {
+        self.integrity.clear();
+
+        // The meta, its precompressed siblings and uploads change after the install.
+        for (filename, digest) in dir_digests(dir) {
+            let is_sibling = [".gz", ".br"].iter().any(|ext| {
+                filename
+                    .strip_suffix(ext)
+                    .is_some_and(|f| dir.join(f).is_file())
+            });
+            let is_upload = filename.starts_with(&format!("{}/", UPLOAD_DIR));
+            if filename != "meta.json" && !is_sibling && !is_upload {
+                self.integrity.insert(filename, digest);
+            }
+        }
     }
 }
 
-impl Server {
-    /// Lists all available plugins by reading their metadata from the `data/plugins` directory.
-    ///
-    /// This function iterates through each subdirectory in the `data/plugins` directory,
-    /// looking for a `meta.json` file. If found and successfully parsed, the plugin's
-    /// metadata is added to a list. Finally, it sends a message containing the list
-    /// of plugins.
-    ///
-    /// # Returns
-    ///
-    /// * `Result<()>` - Indicates success or failure of the operation.
-    pub fn list_plugins(&self) -> Result<()> {
-        let mut plugins = Vec::new();
-
-        if let Ok(entries) = fs::read_dir("data/plugins") {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if !path.is_dir() {
-                        continue;
-                    }
+/// How the config values of a reinstalled plugin were carried over.
+#[derive(Clone, Serialize)]
+pub struct Migration {
+    name: PluginName,
+    /// Configs whose values were kept.
+    kept: Vec<String>,
+    /// Configs that are no longer declared.
+    dropped: Vec<String>,
+    /// Configs that changed their type, or secrets that may be sent to new hosts, reset
+    /// to their default.
+    reset: Vec<String>,
+    /// Configs that are new, set to their default.
+    added: Vec<String>,
+}
 
-                    let file_path = format!("{}/meta.json", path.to_str().unwrap());
-                    if let Ok(content) = fs::read_to_string(file_path) {
-                        match serde_json::from_str::<PluginMeta>(&content) {
-                            Ok(mut m) => {
-                                m.update_script();
-                                plugins.push(m)
-                            }
-                            Err(_) => continue,
-                        };
-                    }
-                }
-            }
+/// Whether a secret may be sent to a host the installed version didn't allow it to.
+fn gains_hosts(old: &Config, new: &Config) -> bool {
+    new.hosts
+        .iter()
+        .flatten()
+        .any(|h| !old.hosts.iter().flatten().any(|o| o == h))
+}
+
+/// Carries over the values of the installed version of a plugin, for configs whose
+/// name and type still match, and for secrets that may only be sent to hosts they could
+/// be sent to before. Every other config is set to its default.
+///
+/// # Parameters
+///
+/// * `name` - The name of the plugin.
+/// * `configs` - The configs of the new version.
+/// * `previous` - The configs of the installed version, if any.
+///
+/// # Returns
+///
+/// * `Option<Migration>` - What happened to the values, if a version was installed.
+fn merge_configs(
+    name: &PluginName,
+    configs: &mut [Config],
+    previous: Option<&[Config]>,
+) -> Option<Migration> {
+    for config in configs.iter_mut() {
+        // A config inheriting a setting stays unset until the user overrides it.
+        if config.value.is_none() && config.inherit.is_none() {
+            config.value = Some(config.default.clone());
         }
+    }
 
-        self.send(Message {
-            type_: MessageType::ListPlugins,
-            data: json!(plugins),
-        })
+    let previous = previous?;
+    let mut migration = Migration {
+        name: name.clone(),
+        kept: vec![],
+        dropped: vec![],
+        reset: vec![],
+        added: vec![],
+    };
+
+    for config in configs.iter_mut() {
+        let old = previous.iter().find(|c| c.name == config.name);
+        let kept = old
+            .filter(|old| old.type_ == config.type_ && !gains_hosts(old, config))
+            .and_then(|old| old.value.as_ref())
+            .and_then(|v| match config.type_.as_str() {
+                // Secrets stay encrypted, as long as they can still be decrypted.
+                "secret" if is_encrypted(v) => decrypt(v, name, &config.name)
+                    .and_then(|plain| normalize(name, config, &plain))
+                    .ok()
+                    .map(|_| v.clone()),
+                _ => normalize(name, config, v).ok(),
+            });
+
+        match (old, kept) {
+            (Some(_), Some(value)) => {
+                config.value = Some(value);
+                migration.kept.push(config.name.clone());
+            }
+            (Some(_), None) => migration.reset.push(config.name.clone()),
+            (None, _) => migration.added.push(config.name.clone()),
+        }
     }
 
-    /// Adds a plugin by creating a new directory in `data/plugins` with the plugin's name,
-    /// and creating a `meta.json` file in it with the plugin's metadata. The metadata is
-    /// either obtained from the `url` field in the `data` parameter or from the `meta`
-    /// field in the `data` parameter, if `url` is not provided. If `meta` is not provided
-    /// or the metadata is not valid, the function returns an error message.
-    ///
-    /// If the plugin already exists, the function removes the old plugin and its
-    /// associated files, and then creates a new one.
-    ///
-    /// # Returns
-    ///
-    /// * `Result<()>` - Indicates success or failure of the operation.
-    pub fn add_plugin(&self, data: Value) -> Result<()> {
-        let meta = match data["url"].as_str() {
-            Some(url) => {
-                let mut meta = None;
+    migration.dropped = previous
+        .iter()
+        .filter(|old| !configs.iter().any(|c| c.name == old.name))
+        .map(|old| old.name.clone())
+        .collect();
 
-                if let Ok(response) = get(url) {
-                    if let Ok(json) = response.json::<Value>() {
-                        meta = Some(json);
-                    }
-                }
+    Some(migration)
+}
+
+/// Reads the meta of an installed plugin.
+pub fn read_installed(name: &PluginName) -> Option<PluginMeta> {
+    let raw = fs::read_to_string(plugin_dir(name)?.join("meta.json")).ok()?;
+    serde_json::from_str(&raw).ok()
+}
 
-                meta
+/// Writes the meta of an installed plugin.
+pub fn write_installed(meta: &PluginMeta) -> std::result::Result<(), String> {
+    let path = plugin_dir(&meta.name).ok_or("Invalid plugin directory.")?;
+    let raw = serde_json::to_string(meta).map_err(|_| "Failed to serialize meta.")?;
+
+    fs::write(path.join("meta.json"), raw).map_err(|_| "Failed to update meta file.".to_string())
+}
+
+/// Reads the metas of every installed plugin, skipping the ones that are invalid.
+pub fn installed_plugins() -> Vec<PluginMeta> {
+    let mut plugins = Vec::new();
+
+    let entries = data_path("plugins").and_then(|p| fs::read_dir(p).ok());
+    if let Some(entries) = entries {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_dir() {
+                continue;
             }
-            None => data.get("meta").cloned(),
-        };
 
-        if meta.is_none() {
-            return self.send(Message::error("Failed to get meta."));
+            if let Ok(content) = fs::read_to_string(path.join("meta.json")) {
+                match serde_json::from_str::<PluginMeta>(&content) {
+                    Ok(m) => plugins.push(m),
+                    Err(_) => warn!(
+                        "Skipping plugin in {} with an invalid meta file.",
+                        path.display()
+                    ),
+                };
+            }
         }
+    }
+
+    plugins
+}
 
-        let mut parsed = match from_value::<PluginMeta>(meta.unwrap()) {
-            Ok(p) => p,
-            Err(_) => return self.send(Message::error("Failed to parse meta.")),
+/// Downloads the meta of a plugin from where it was installed from.
+pub fn upstream_meta(source: &InstallSource) -> std::result::Result<Value, String> {
+    let staging = StagingDir::new().ok_or("Failed to create plugin directory.")?;
+    Ok(read_meta(&source.install_data(), staging.path())?.meta)
+}
+
+/// Writes a script into the plugin directory. A script that is neither inline nor
+/// fetched from a URL must already be part of the package.
+fn write_script(
+    script: &Script,
+    dir: &Path,
+    filename: &str,
+    error_msg_fetch: &str,
+    error_msg_write: &str,
+) -> std::result::Result<(), String> {
+    if script.inline.is_none() && script.url.is_none() {
+        return match fs::read(dir.join(filename)) {
+            Ok(bytes) => script.check(&bytes),
+            Err(_) => Err(error_msg_fetch.to_string()),
         };
+    }
 
-        if parsed.script.inline.is_none() && parsed.script.url.is_none() {
-            return self.send(Message::error("Failed to get script."));
-        }
+    let script = script.fetch()?;
+    fs::write(dir.join(filename), script).map_err(|_| error_msg_write.to_string())
+}
+
+/// The meta of a plugin to install, as read by `read_meta`.
+struct MetaSource {
+    meta: Value,
+    /// The meta exactly as it was downloaded, which is what publishers sign.
+    raw: Vec<u8>,
+    /// The location the meta or package was loaded from. Relative URLs in the meta are
+    /// resolved against it.
+    base: Option<Url>,
+}
 
-        let mut configs = parsed.configs.clone().unwrap_or(vec![]);
-
-        configs.insert(
-            0,
-            Config {
-                name: "enabled".to_string(),
-                type_: "checkbox".to_string(),
-                default: Value::Bool(false),
-                value: None,
-                hint: None,
-                options: None,
-            },
-        );
-
-        for config in configs.iter_mut() {
-            if config.value.is_none() {
-                config.value = Some(config.default.clone());
+/// Reads the meta of the plugin to install, unpacking its package into `staging` if
+/// one is given.
+fn read_meta(data: &Value, staging: &Path) -> std::result::Result<MetaSource, String> {
+    if let Some(package) = data.get("package") {
+        let (bytes, base) = match (package["url"].as_str(), package["data"].as_str()) {
+            (Some(location), _) => {
+                let url = parse_location(location).ok_or("Failed to get package.")?;
+                (fetch_bytes(url.as_str(), MAX_PACKAGE_SIZE)?, Some(url))
             }
+            (None, Some(encoded)) => (
+                STANDARD
+                    .decode(encoded)
+                    .map_err(|_| "Failed to decode package.")?,
+                None,
+            ),
+            _ => return Err("Failed to get package.".to_string()),
+        };
+
+        unpack(&bytes, staging)?;
+
+        let raw = fs::read(staging.join("meta.json")).map_err(|_| "Failed to read meta file.")?;
+        let meta = serde_json::from_slice(&raw).map_err(|_| "Failed to parse meta.")?;
+        return Ok(MetaSource { meta, raw, base });
+    }
+
+    match data["url"].as_str() {
+        Some(location) => {
+            let url = parse_location(location).ok_or("Failed to get meta.")?;
+            let raw = fetch_bytes(url.as_str(), MAX_SCRIPT_SIZE)?;
+            let meta = serde_json::from_slice(&raw).map_err(|_| "Failed to parse meta.")?;
+
+            Ok(MetaSource {
+                meta,
+                raw,
+                base: Some(url),
+            })
         }
+        None => match data.get("meta") {
+            Some(meta) => Ok(MetaSource {
+                meta: meta.clone(),
+                raw: serde_json::to_vec(meta).unwrap(),
+                base: None,
+            }),
+            None => Err("Failed to get meta.".to_string()),
+        },
+    }
+}
 
-        parsed.configs = Some(configs);
+/// Returns the detached signature of the plugin to install, either given in the data
+/// or published next to the meta or package as a `.sig` file.
+fn read_signature(data: &Value, base: Option<&Url>) -> Option<String> {
+    if let Some(signature) = data["signature"].as_str() {
+        return Some(signature.to_string());
+    }
+
+    let url = format!("{}.sig", base?);
+    fetch_text(&url).ok()
+}
 
-        let dir_path = format!("data/plugins/{}", parsed.name);
-        if Path::new(dir_path.as_str()).exists() {
-            if fs::remove_dir_all(&dir_path).is_err() {
-                return self.send(Message::error("Failed to remove old plugin."));
-            };
+/// Installs a plugin as described by the data of an `addPlugin` message. A plugin
+/// that is already installed keeps its config values and is moved to `data/backups`
+/// so it can be rolled back.
+///
+/// # Returns
+///
+/// * `Result<(PluginMeta, Option<Migration>), String>` - The installed plugin and how
+///   the values of the installed version were carried over, or the reason it failed.
+pub fn install_plugin(
+    data: &Value,
+) -> std::result::Result<(PluginMeta, Option<Migration>), String> {
+    let staging = StagingDir::new().ok_or("Failed to create plugin directory.")?;
+
+    let source = read_meta(data, staging.path())?;
+    let base = source.base;
+    let install_source = match (data.get("package"), &base) {
+        (Some(_), Some(url)) => Some(InstallSource::Package(url.to_string())),
+        (None, Some(url)) => Some(InstallSource::Meta(url.to_string())),
+        _ => None,
+    };
+    let mut parsed = from_value::<PluginMeta>(source.meta).map_err(|_| "Failed to parse meta.")?;
+
+    parsed.script.resolve(base.as_ref())?;
+    if let Some(bg_script) = parsed.background_script.as_mut() {
+        bg_script.resolve(base.as_ref())?;
+    }
+    if let Some(editor_script) = parsed.editor_script.as_mut() {
+        editor_script.resolve(base.as_ref())?;
+    }
+
+    let mut configs = parsed.configs.clone().unwrap_or(vec![]);
+
+    configs.insert(
+        0,
+        Config {
+            name: "enabled".to_string(),
+            type_: "checkbox".to_string(),
+            default: Value::Bool(false),
+            required: true,
+            ..Default::default()
+        },
+    );
+    validate_declarations(&configs, parsed.editor_script.is_some())?;
+    normalize_defaults(&parsed.name, &mut configs)?;
+
+    write_script(
+        &parsed.script,
+        staging.path(),
+        "index.js",
+        "Failed to get the script file.",
+        "Failed to create script file.",
+    )?;
+
+    if let Some(bg_script) = &parsed.background_script {
+        write_script(
+            bg_script,
+            staging.path(),
+            "background.js",
+            "Failed to get the background script file.",
+            "Failed to create background script file.",
+        )?;
+    }
+
+    if let Some(editor_script) = &parsed.editor_script {
+        write_script(
+            editor_script,
+            staging.path(),
+            "editor.js",
+            "Failed to get the editor script file.",
+            "Failed to create editor script file.",
+        )?;
+    }
+
+    // Verify the files as published, before any imports are rewritten.
+    let store = TrustStore::load()?;
+    let signature = match store.policy() {
+        TrustPolicy::Off => None,
+        _ => read_signature(data, base.as_ref()),
+    };
+    let trust = verify(
+        &store,
+        &manifest(staging.path(), &source.raw),
+        signature.as_deref(),
+    );
+
+    match (store.policy(), trust.status) {
+        (_, TrustStatus::Verified | TrustStatus::Unchecked) => {}
+        (TrustPolicy::Require, _) => {
+            return Err("The plugin is not signed by a trusted publisher.".to_string())
         }
+        _ => warn!(
+            "Installing plugin {} which is not signed by a trusted publisher.",
+            parsed.name
+        ),
+    }
+    parsed.trust = Some(trust);
+    parsed.source = install_source;
 
-        if fs::create_dir_all(dir_path.as_str()).is_err() {
-            return self.send(Message::error("Failed to create plugin directory."));
-        };
+    vendor_modules(staging.path(), "index.js", parsed.script.source_url())?;
+    if let Some(bg_script) = &parsed.background_script {
+        vendor_modules(staging.path(), "background.js", bg_script.source_url())?;
+    }
+    if let Some(editor_script) = &parsed.editor_script {
+        vendor_modules(staging.path(), "editor.js", editor_script.source_url())?;
+    }
+    parsed.record_integrity(staging.path());
+
+    // Everything is fetched, so the installed version is only read and replaced now.
+    let _guard = lock_switch();
+    let previous = read_installed(&parsed.name).map(|m| m.configs.unwrap_or_default());
+    let migration = merge_configs(&parsed.name, &mut configs, previous.as_deref());
+    parsed.configs = Some(configs);
+
+    let dir_path = plugin_dir(&parsed.name).ok_or("Invalid plugin directory.")?;
+    copy_uploads(&dir_path, staging.path())?;
+    prune_uploads(
+        staging.path(),
+        &parsed.name,
+        parsed.configs.as_deref().unwrap_or_default(),
+    );
+
+    let raw = serde_json::to_string(&parsed).map_err(|_| "Failed to serialize meta.")?;
+    fs::write(staging.path().join("meta.json"), raw)
+        .map_err(|_| "Failed to write to meta file.")?;
+
+    if dir_path.exists() {
+        let backup = backup_dir(&parsed.name).ok_or("Invalid backup directory.")?;
+
+        if backup.exists() && fs::remove_dir_all(&backup).is_err() {
+            return Err("Failed to remove old backup.".to_string());
+        }
+        fs::create_dir_all(backup.parent().unwrap())
+            .and_then(|_| fs::rename(&dir_path, &backup))
+            .map_err(|_| "Failed to back up old plugin.")?;
+    }
+
+    fs::create_dir_all(dir_path.parent().unwrap())
+        .and_then(|_| fs::rename(staging.path(), &dir_path))
+        .map_err(|_| "Failed to create plugin directory.")?;
+
+    precompress_dir(&dir_path);
+    append_to_order(parsed.name.as_str());
+
+    // Saved values of secrets that weren't carried over may be from a version that
+    // allowed other hosts.
+    let forgotten: Vec<String> = parsed
+        .configs
+        .iter()
+        .flatten()
+        .filter(|c| c.type_ == "secret")
+        .filter(|c| !migration.as_ref().is_some_and(|m| m.kept.contains(&c.name)))
+        .map(|c| c.name.clone())
+        .collect();
+    if !forgotten.is_empty() {
+        forget_instance_values(&parsed.name, &forgotten);
+        forget_preset_values(&parsed.name, &forgotten);
+        forget_scene_values(&parsed.name, &forgotten);
+    }
 
-        let file_path = format!("{}/meta.json", dir_path);
-        let mut file = match File::create(file_path) {
-            Ok(f) => f,
-            Err(_) => {
-                fs::remove_dir_all(&dir_path).unwrap();
-                return self.send(Message::error("Failed to create meta file."));
+    Ok((parsed, migration))
+}
+
+/// Checks new values against the configs of a plugin and sets them. Secret values are
+/// encrypted, and sending back the masked value of a secret leaves it unchanged.
+///
+/// # Returns
+///
+/// * `std::result::Result<(), Vec<FieldError>>` - The error of each invalid value, in
+///   which case nothing is changed.
+pub(crate) fn apply_values(
+    plugin: &PluginName,
+    configs: &mut [Config],
+    values: &[ConfigValue],
+) -> std::result::Result<(), Vec<FieldError>> {
+    let mut normalized = HashMap::new();
+    let mut errors = Vec::new();
+    for cv in values.iter() {
+        let result = match configs.iter().find(|c| c.name == cv.name) {
+            Some(config) if config.type_ == "secret" && cv.value == MASK => continue,
+            // A secret saved in a preset or scene is already encrypted, and is only
+            // accepted if it was encrypted for this config of this plugin.
+            Some(config) if config.type_ == "secret" && is_encrypted(&cv.value) => {
+                decrypt(&cv.value, plugin, &config.name)
+                    .and_then(|v| encrypt(&v, plugin, &config.name))
             }
+            Some(config) if config.type_ == "secret" => normalize(plugin, config, &cv.value)
+                .and_then(|v| match v.is_null() || v == "" {
+                    true => Ok(v),
+                    false => encrypt(&v, plugin, &config.name),
+                }),
+            Some(config) => normalize(plugin, config, &cv.value),
+            None => Err("Unknown config.".to_string()),
         };
 
-        let raw = match serde_json::to_string(&parsed) {
-            Ok(r) => r,
-            Err(_) => {
-                fs::remove_dir_all(&dir_path).unwrap();
-                return self.send(Message::error("Failed to serialize meta."));
+        match result {
+            Ok(value) => {
+                normalized.insert(cv.name.clone(), value);
             }
-        };
+            Err(message) => errors.push(FieldError {
+                name: cv.name.clone(),
+                message,
+            }),
+        }
+    }
 
-        if file.write_all(raw.as_bytes()).is_err() {
-            fs::remove_dir_all(&dir_path).unwrap();
-            return self.send(Message::error("Failed to write to meta file."));
-        };
+    if !errors.is_empty() {
+        return Err(errors);
+    }
 
-        macro_rules! process_script {
-            ($script:expr, $dir_path:expr, $filename:expr, $error_msg_fetch:expr, $error_msg_write:expr) => {
-                let script = match $script.inline.as_ref() {
-                    Some(s) => s.clone(),
-                    None => {
-                        let url = $script.url.as_ref().unwrap();
-                        let resp = match get(url) {
-                            Ok(r) => r,
-                            Err(_) => return self.send(Message::error($error_msg_fetch)),
-                        };
-                        match resp.text() {
-                            Ok(t) => t,
-                            Err(_) => return self.send(Message::error($error_msg_fetch)),
-                        }
-                    }
-                };
+    for config in configs.iter_mut() {
+        if let Some(value) = normalized.remove(&config.name) {
+            config.value = Some(value);
+        }
+    }
 
-                let file_path = format!("{}/{}", $dir_path, $filename);
-                let mut file = match File::create(&file_path) {
-                    Ok(f) => f,
-                    Err(_) => {
-                        fs::remove_dir_all(&$dir_path).unwrap();
-                        return self.send(Message::error($error_msg_write));
-                    }
-                };
-                if file.write_all(script.as_bytes()).is_err() {
-                    fs::remove_dir_all(&$dir_path).unwrap();
-                    return self.send(Message::error($error_msg_write));
-                };
-            };
+    Ok(())
+}
+
+impl Server {
+    /// Broadcasts a newly installed plugin, and tells the sender how the values of the
+    /// previously installed version were carried over.
+    pub(crate) fn announce_install(
+        &self,
+        result: std::result::Result<(PluginMeta, Option<Migration>), String>,
+    ) -> Result<()> {
+        let (meta, migration) = match result {
+            Ok(r) => r,
+            Err(e) => return self.send(Message::error(&e)),
+        };
+
+        for entry in client_entries(meta) {
+            self.broadcast(Message {
+                type_: MessageType::AddPlugin,
+                data: entry,
+            })?;
         }
 
-        process_script!(
-            parsed.script,
-            dir_path,
-            "index.js",
-            "Failed to get the script file.",
-            "Failed to create script file."
-        );
-
-        if let Some(bg_script) = &parsed.background_script {
-            process_script!(
-                bg_script,
-                dir_path,
-                "background.js",
-                "Failed to get the background script file.",
-                "Failed to create background script file."
-            );
+        match migration {
+            Some(migration) => self.send(Message {
+                type_: MessageType::PluginMigration,
+                data: to_value(migration).unwrap(),
+            }),
+            None => Ok(()),
         }
-        parsed.update_script();
+    }
 
-        self.broadcast(Message {
-            type_: MessageType::AddPlugin,
-            data: to_value(parsed).unwrap(),
+    /// Lists all available plugins by reading their metadata from the `data/plugins` directory.
+    ///
+    /// This function iterates through each subdirectory in the `data/plugins` directory,
+    /// looking for a `meta.json` file. If found and successfully parsed, the plugin's
+    /// metadata is added to a list. Finally, it sends a message containing the list
+    /// of plugins.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn list_plugins(&self) -> Result<()> {
+        let mut plugins: Vec<Value> = installed_plugins()
+            .into_iter()
+            .flat_map(client_entries)
+            .collect();
+        // Plugins without a position come last.
+        plugins.sort_by_key(|p| {
+            (
+                p["order"].as_u64().unwrap_or(u64::MAX),
+                p["id"].as_str().unwrap_or_default().to_string(),
+            )
+        });
+
+        self.send(Message {
+            type_: MessageType::ListPlugins,
+            data: json!(plugins),
         })
     }
 
+    /// Adds a plugin by creating a new directory in `data/plugins` with the plugin's name,
+    /// and creating a `meta.json` file in it with the plugin's metadata. The metadata is
+    /// obtained from the `package` field in the `data` parameter, from the `url` field, or
+    /// from the `meta` field if neither is provided. If no metadata is found or it is not
+    /// valid, the function returns an error message.
+    ///
+    /// A `package` is a zip or tar.gz archive containing `meta.json` and the plugin's
+    /// assets, given either as `{ "url": ... }` or as base64 encoded `{ "data": ... }`.
+    ///
+    /// If the plugin already exists, the old plugin is replaced. Values of configs whose
+    /// name and type still match are kept, and the sender is told which values were
+    /// kept, dropped or reset in a `pluginMigration` message.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn add_plugin(&self, data: Value) -> Result<()> {
+        self.announce_install(install_plugin(&data))
+    }
+
     /// Removes a plugin.
     ///
     /// # Parameters
@@ -277,19 +821,30 @@ impl Server {
     ///
     /// * `Result<()>` - Indicates success or failure of the operation.
     pub fn remove_plugin(&self, data: Value) -> Result<()> {
-        let name = match data["name"].as_str() {
+        let name = match data["name"].as_str().and_then(PluginName::parse) {
             Some(n) => n,
             None => return self.send(Message::error("Failed to get plugin.")),
         };
 
-        let dir_path = format!("data/plugins/{}", name);
-        if !Path::new(dir_path.as_str()).exists() {
+        let dir_path = match plugin_dir(&name) {
+            Some(d) => d,
+            None => return self.send(Message::error("Invalid plugin directory.")),
+        };
+
+        let _guard = lock_switch();
+        if !dir_path.exists() {
             return self.send(Message::error("Plugin not found."));
         }
 
         if fs::remove_dir_all(&dir_path).is_err() {
             return self.send(Message::error("Failed to remove plugin."));
         };
+        if let Some(backup) = backup_dir(&name).filter(|b| b.exists()) {
+            let _ = fs::remove_dir_all(backup);
+        }
+        remove_presets(&name);
+        remove_from_order(&plugin_ids(&name));
+        remove_instances(&name);
 
         self.broadcast(Message {
             type_: MessageType::RemovePlugin,
@@ -305,12 +860,15 @@ impl Server {
     ///
     /// * `data` - A JSON object with a `name` property and a `configs` property.
     ///   The `configs` property should be a JSON array of objects with `name` and `value` properties.
+    ///   If any value doesn't match the type and constraints of its config, nothing is
+    ///   changed and the sender gets a `validationError` message with an error per field.
+    ///   An optional `id` property configures one of the instances of the plugin instead.
     ///
     /// # Returns
     ///
     /// * `Result<()>` - Indicates success or failure of the operation.
     pub fn config_plugin(&self, data: Value) -> Result<()> {
-        let name = match data["name"].as_str() {
+        let name = match data["name"].as_str().and_then(PluginName::parse) {
             Some(n) => n,
             None => return self.send(Message::error("Failed to get plugin.")),
         };
@@ -320,7 +878,41 @@ impl Server {
             Err(_) => return self.send(Message::error("Failed to parse configs.")),
         };
 
-        let file_path = format!("data/plugins/{}/meta.json", name);
+        self.update_configs(&name, data["id"].as_str(), &configs)
+    }
+
+    /// Sends the errors of invalid config values with a `validationError` message.
+    pub(crate) fn send_validation_errors(
+        &self,
+        name: &PluginName,
+        errors: Vec<FieldError>,
+    ) -> Result<()> {
+        self.send(Message {
+            type_: MessageType::ValidationError,
+            data: json!({
+                "name": name,
+                "errors": errors,
+            }),
+        })
+    }
+
+    /// Sets config values of an installed plugin, or of one of its instances, saves them
+    /// and broadcasts the updated meta with a `configPlugin` message.
+    pub(crate) fn update_configs(
+        &self,
+        name: &PluginName,
+        id: Option<&str>,
+        configs: &[ConfigValue],
+    ) -> Result<()> {
+        if let Some(id) = instance_id(name, id) {
+            return self.update_instance_configs(name, id, configs);
+        }
+        let _guard = lock_switch();
+
+        let file_path = match plugin_dir(name) {
+            Some(d) => d.join("meta.json"),
+            None => return self.send(Message::error("Invalid plugin directory.")),
+        };
         let raw = match fs::read_to_string(&file_path) {
             Ok(r) => r,
             Err(_) => return self.send(Message::error("Failed to read meta file.")),
@@ -331,16 +923,9 @@ impl Server {
             Err(_) => return self.send(Message::error("Failed to parse meta file.")),
         };
 
-        let mut configs_map = HashMap::new();
-        for cv in configs.iter() {
-            configs_map.insert(cv.name.clone(), cv.value.clone());
-        }
-
-        let mut meta_configs = meta.configs.clone().unwrap();
-        for config in meta_configs.iter_mut() {
-            if let Some(value) = configs_map.get(&config.name) {
-                config.value = Some(value.clone());
-            }
+        let mut meta_configs = meta.configs.clone().unwrap_or_default();
+        if let Err(errors) = apply_values(name, &mut meta_configs, configs) {
+            return self.send_validation_errors(name, errors);
         }
         meta.configs = Some(meta_configs);
 
@@ -351,11 +936,12 @@ impl Server {
 
         match fs::write(file_path, raw) {
             Ok(_) => {
-                meta.update_script();
-
+                if let Some(dir) = plugin_dir(name) {
+                    prune_uploads(&dir, name, meta.configs.as_deref().unwrap_or_default());
+                }
                 self.broadcast(Message {
                     type_: MessageType::ConfigPlugin,
-                    data: json!(meta),
+                    data: meta.into_client(),
                 })
             }
             Err(_) => self.send(Message::error("Failed to update meta file.")),
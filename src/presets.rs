@@ -0,0 +1,286 @@
+use std::{fs, path::PathBuf};
+
+use crate::instances::find_configs;
+use crate::paths::{data_path, PluginName};
+use crate::plugin::{apply_values, ConfigValue};
+use crate::secrets::{is_encrypted, MASK};
+use crate::{Message, MessageType, Server};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_value, json, Value};
+use ws::Result;
+
+/// The longest name of a preset, in characters.
+const MAX_PRESET_NAME_LENGTH: usize = 64;
+
+/// A named set of config values of a plugin.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Preset {
+    name: String,
+    configs: Vec<ConfigValue>,
+}
+
+impl Preset {
+    /// Returns the preset as sent to clients, with the values of secrets masked.
+    fn masked(mut self) -> Self {
+        for cv in self.configs.iter_mut().filter(|cv| is_encrypted(&cv.value)) {
+            cv.value = Value::String(MASK.to_string());
+        }
+
+        self
+    }
+}
+
+/// Presets are kept outside the plugin directory, so they survive reinstalls and aren't
+/// served over HTTP.
+fn presets_path(plugin: &PluginName) -> Option<PathBuf> {
+    data_path(&format!("presets/{}.json", plugin))
+}
+
+fn read_presets(plugin: &PluginName) -> Vec<Preset> {
+    let raw = match presets_path(plugin).and_then(|p| fs::read_to_string(p).ok()) {
+        Some(r) => r,
+        None => return vec![],
+    };
+
+    serde_json::from_str(&raw).unwrap_or_else(|_| {
+        warn!("Failed to parse the presets of {}.", plugin);
+        vec![]
+    })
+}
+
+fn write_presets(plugin: &PluginName, presets: &[Preset]) -> std::result::Result<(), String> {
+    let path = presets_path(plugin).ok_or("Invalid presets path.")?;
+
+    if presets.is_empty() {
+        let _ = fs::remove_file(&path);
+        return Ok(());
+    }
+
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(&path, json!(presets).to_string()))
+        .map_err(|_| "Failed to write presets.".to_string())
+}
+
+/// Returns the values saved in every preset of a plugin.
+pub fn preset_values(plugin: &PluginName) -> Vec<ConfigValue> {
+    read_presets(plugin)
+        .into_iter()
+        .flat_map(|p| p.configs)
+        .collect()
+}
+
+/// Removes the values of configs from every preset of a plugin, such as secrets that
+/// weren't carried over on reinstall.
+pub fn forget_preset_values(plugin: &PluginName, names: &[String]) {
+    let mut presets = read_presets(plugin);
+    let mut changed = false;
+    for preset in presets.iter_mut() {
+        let count = preset.configs.len();
+        preset.configs.retain(|cv| !names.contains(&cv.name));
+        changed |= preset.configs.len() != count;
+    }
+
+    if changed {
+        if let Err(e) = write_presets(plugin, &presets) {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Removes the presets of a plugin, when it is removed.
+pub fn remove_presets(plugin: &PluginName) {
+    if let Some(path) = presets_path(plugin) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Parses the `name` of the plugin and the name of the `preset` of a message.
+fn parse_names(data: &Value) -> Option<(PluginName, String)> {
+    let plugin = data["name"].as_str().and_then(PluginName::parse)?;
+    let preset = data["preset"].as_str()?.trim();
+
+    let valid = !preset.is_empty() && preset.chars().count() <= MAX_PRESET_NAME_LENGTH;
+    valid.then(|| (plugin, preset.to_string()))
+}
+
+/// Returns a `listPresets` message with the presets of a plugin.
+fn presets_message(plugin: &PluginName) -> Message {
+    let presets: Vec<Preset> = read_presets(plugin)
+        .into_iter()
+        .map(Preset::masked)
+        .collect();
+
+    Message {
+        type_: MessageType::ListPresets,
+        data: json!({
+            "name": plugin,
+            "presets": presets,
+        }),
+    }
+}
+
+impl Server {
+    /// Lists the presets of a plugin.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with a `name` property.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn list_presets(&self, data: Value) -> Result<()> {
+        let plugin = match data["name"].as_str().and_then(PluginName::parse) {
+            Some(p) => p,
+            None => return self.send(Message::error("Failed to get plugin.")),
+        };
+
+        self.send(presets_message(&plugin))
+    }
+
+    /// Saves a preset of a plugin, replacing any preset with the same name.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the plugin, the name of the `preset`,
+    ///   and optionally `configs`, an array of objects with `name` and `value`
+    ///   properties. Without `configs`, the current values of the plugin, or of the
+    ///   instance with the given `id`, are saved.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn save_preset(&self, data: Value) -> Result<()> {
+        let (plugin, preset_name) = match parse_names(&data) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get preset.")),
+        };
+
+        let mut configs = match find_configs(&plugin, data["id"].as_str()) {
+            Some(c) => c,
+            None => return self.send(Message::error("Plugin not found.")),
+        };
+
+        let values: Vec<ConfigValue> = match data.get("configs") {
+            Some(v) => {
+                let values = match from_value::<Vec<ConfigValue>>(v.clone()) {
+                    Ok(v) => v,
+                    Err(_) => return self.send(Message::error("Failed to parse configs.")),
+                };
+
+                // Saved in the same form as the configs, so secrets are encrypted.
+                if let Err(errors) = apply_values(&plugin, &mut configs, &values) {
+                    return self.send_validation_errors(&plugin, errors);
+                }
+
+                configs
+                    .into_iter()
+                    .filter(|c| values.iter().any(|v| v.name == c.name))
+                    .map(|c| ConfigValue {
+                        name: c.name,
+                        value: c.value.unwrap_or_default(),
+                    })
+                    .collect()
+            }
+            // Whether the plugin is shown isn't part of its presets.
+            None => configs
+                .into_iter()
+                .filter(|c| c.name != "enabled")
+                .filter_map(|c| {
+                    c.value.map(|value| ConfigValue {
+                        name: c.name,
+                        value,
+                    })
+                })
+                .collect(),
+        };
+
+        let mut presets = read_presets(&plugin);
+        let preset = Preset {
+            name: preset_name,
+            configs: values,
+        };
+        match presets.iter_mut().find(|p| p.name == preset.name) {
+            Some(existing) => *existing = preset,
+            None => presets.push(preset),
+        }
+
+        if let Err(e) = write_presets(&plugin, &presets) {
+            return self.send(Message::error(&e));
+        }
+
+        self.broadcast(presets_message(&plugin))
+    }
+
+    /// Sets the config values of a plugin to those of a preset. Values of configs the
+    /// plugin no longer declares are skipped.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the plugin, the name of the `preset`
+    ///   and optionally the `id` of the instance to apply it to.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn apply_preset(&self, data: Value) -> Result<()> {
+        let (plugin, preset_name) = match parse_names(&data) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get preset.")),
+        };
+
+        let configs = match find_configs(&plugin, data["id"].as_str()) {
+            Some(c) => c,
+            None => return self.send(Message::error("Plugin not found.")),
+        };
+
+        let preset = match read_presets(&plugin)
+            .into_iter()
+            .find(|p| p.name == preset_name)
+        {
+            Some(p) => p,
+            None => return self.send(Message::error("Preset not found.")),
+        };
+
+        let values: Vec<ConfigValue> = preset
+            .configs
+            .into_iter()
+            .filter(|v| configs.iter().any(|c| c.name == v.name))
+            .collect();
+
+        self.update_configs(&plugin, data["id"].as_str(), &values)
+    }
+
+    /// Deletes a preset of a plugin.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the plugin and the name of the
+    ///   `preset`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn delete_preset(&self, data: Value) -> Result<()> {
+        let (plugin, preset_name) = match parse_names(&data) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get preset.")),
+        };
+
+        let mut presets = read_presets(&plugin);
+        let count = presets.len();
+        presets.retain(|p| p.name != preset_name);
+
+        if presets.len() == count {
+            return self.send(Message::error("Preset not found."));
+        }
+
+        if let Err(e) = write_presets(&plugin, &presets) {
+            return self.send(Message::error(&e));
+        }
+
+        self.broadcast(presets_message(&plugin))
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::instances::find_configs;
+use crate::paths::PluginName;
+use crate::plugin::Config;
+use crate::secrets::{decrypt, is_encrypted};
+use crate::{Message, MessageType, Server};
+
+use regex::{Captures, Regex};
+use reqwest::{blocking::Client, redirect::Policy, Method};
+use serde::Deserialize;
+use serde_json::{from_value, json, Value};
+use url::Url;
+use ws::{Message as WSMessage, Result};
+
+/// The largest response passed back to the plugin.
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+/// How long a request may take, including reading the response.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// The most requests running at the same time.
+const MAX_IN_FLIGHT: usize = 8;
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// A request a plugin makes through the server, so that its secrets are only ever
+/// filled in here.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FetchRequest {
+    name: String,
+    #[serde(default)]
+    id: Option<String>,
+    /// Passed back with the response, so the plugin can tell its requests apart.
+    #[serde(default)]
+    request_id: Value,
+    url: String,
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// Returns a secret config and its decrypted value.
+fn secret_value<'a>(
+    name: &str,
+    plugin: &PluginName,
+    configs: &'a [Config],
+) -> std::result::Result<(&'a Config, String), String> {
+    let config = configs
+        .iter()
+        .find(|c| c.name == name && c.type_ == "secret")
+        .ok_or(format!("Unknown secret \"{}\".", name))?;
+    let value = config
+        .value
+        .as_ref()
+        .filter(|v| is_encrypted(v))
+        .ok_or(format!("The secret \"{}\" is not set.", name))?;
+
+    let plain = decrypt(value, plugin, name)?;
+    Ok((config, plain.as_str().unwrap_or_default().to_string()))
+}
+
+/// Replaces every `{{secret:<name>}}` in `text` with the value of that secret, and
+/// records which secrets were used.
+fn substitute<'a>(
+    text: &str,
+    plugin: &PluginName,
+    configs: &'a [Config],
+    used: &mut Vec<&'a Config>,
+) -> std::result::Result<String, String> {
+    let re = Regex::new(r"\{\{secret:([A-Za-z0-9_.-]+)\}\}").unwrap();
+
+    let mut error = None;
+    let replaced = re.replace_all(text, |caps: &Captures| {
+        match secret_value(&caps[1], plugin, configs) {
+            Ok((config, value)) => {
+                used.push(config);
+                value
+            }
+            Err(e) => {
+                error.get_or_insert(e);
+                String::new()
+            }
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(replaced.into_owned()),
+    }
+}
+
+/// Sends a request with the secrets filled in. Requests may only go to the hosts
+/// declared by the secret configs of the plugin, and each secret only to its own hosts.
+///
+/// # Returns
+///
+/// * `Result<Value, String>` - The `status`, `contentType` and `body` of the response,
+///   or the reason it failed. Errors never include the URL, which may hold a secret.
+fn perform(
+    request: &FetchRequest,
+    plugin: &PluginName,
+    configs: &[Config],
+) -> std::result::Result<Value, String> {
+    let mut used = Vec::new();
+    let url = substitute(&request.url, plugin, configs, &mut used)?;
+    let headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), substitute(value, plugin, configs, &mut used)?)))
+        .collect::<std::result::Result<Vec<(String, String)>, String>>()?;
+    let body = match &request.body {
+        Some(body) => Some(substitute(body, plugin, configs, &mut used)?),
+        None => None,
+    };
+
+    let url = Url::parse(&url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .ok_or("Invalid URL.")?;
+    let host = url.host_str().ok_or("Invalid URL.")?.to_ascii_lowercase();
+
+    let allows_host = |config: &Config| config.hosts.iter().flatten().any(|h| *h == host);
+    if !configs
+        .iter()
+        .any(|c| c.type_ == "secret" && allows_host(c))
+    {
+        return Err(format!("The plugin may not send requests to {}.", host));
+    }
+    if let Some(config) = used.iter().find(|c| !allows_host(c)) {
+        return Err(format!(
+            "The secret \"{}\" may not be sent to {}.",
+            config.name, host
+        ));
+    }
+
+    let method = Method::from_bytes(request.method.as_deref().unwrap_or("GET").as_bytes())
+        .map_err(|_| "Invalid method.")?;
+    // A redirect could carry the secrets to another host.
+    let client = Client::builder()
+        .redirect(Policy::none())
+        .timeout(TIMEOUT)
+        .build()
+        .map_err(|_| "Failed to create client.")?;
+
+    let mut builder = client.request(method, url);
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+    if let Some(body) = body {
+        builder = builder.body(body);
+    }
+
+    let failed = || format!("Failed to fetch from {}.", host);
+    let resp = builder.send().map_err(|_| failed())?;
+    let status = resp.status().as_u16();
+    let content_type = resp
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    let mut bytes = Vec::new();
+    resp.take(MAX_RESPONSE_SIZE + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|_| failed())?;
+    if bytes.len() as u64 > MAX_RESPONSE_SIZE {
+        return Err(format!(
+            "The response is larger than {} bytes.",
+            MAX_RESPONSE_SIZE
+        ));
+    }
+
+    Ok(json!({
+        "status": status,
+        "contentType": content_type,
+        "body": String::from_utf8_lossy(&bytes),
+    }))
+}
+
+impl Server {
+    /// Sends an HTTP request for a plugin with the values of its secrets filled in, so
+    /// that secrets are used without ever reaching a client. The request runs on a
+    /// worker thread, and the sender gets the response in a `pluginFetch` message.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the plugin, optionally the `id` of
+    ///   the instance whose secrets are used, a `requestId` passed back with the
+    ///   response, and the `url`, `method`, `headers` and `body` of the request, in
+    ///   which `{{secret:<config>}}` is replaced with the value of that secret.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn plugin_fetch(&self, data: Value) -> Result<()> {
+        let request = match from_value::<FetchRequest>(data) {
+            Ok(r) => r,
+            Err(_) => return self.send(Message::error("Failed to parse request.")),
+        };
+        let name = match PluginName::parse(&request.name) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get plugin.")),
+        };
+        let configs = match find_configs(&name, request.id.as_deref()) {
+            Some(c) => c,
+            None => return self.send(Message::error("Plugin not found.")),
+        };
+
+        if IN_FLIGHT.fetch_add(1, Ordering::SeqCst) >= MAX_IN_FLIGHT {
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+            return self.send(Message::error("Too many requests."));
+        }
+
+        let out = self.out.clone();
+        thread::spawn(move || {
+            let mut data = match perform(&request, &name, &configs) {
+                Ok(response) => response,
+                Err(e) => json!({ "error": e }),
+            };
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+
+            data["name"] = json!(name);
+            data["requestId"] = request.request_id;
+            let message = Message {
+                type_: MessageType::PluginFetch,
+                data,
+            };
+            let _ = out.send(WSMessage::Text(serde_json::to_string(&message).unwrap()));
+        });
+
+        Ok(())
+    }
+}
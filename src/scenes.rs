@@ -0,0 +1,635 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use crate::compress::remove_siblings;
+use crate::instances::{client_entries, instances_path, read_instances, Instance};
+use crate::paths::{data_path, plugin_dir, PluginName};
+use crate::plugin::{apply_values, installed_plugins, ConfigValue, PluginMeta};
+use crate::secrets::{is_encrypted, MASK};
+use crate::serve::percent_decode;
+use crate::style::{record_style, saved_style};
+use crate::upload::prune_uploads;
+use crate::validation::parse_time;
+use crate::{Message, MessageType, Server};
+
+use chrono::{Local, Timelike};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use ws::{Message as WSMessage, Request, Response, Result, Sender};
+
+/// The longest name of a scene, in characters.
+const MAX_SCENE_NAME_LENGTH: usize = 64;
+/// The suffix of the files written before a scene is switched to.
+const PENDING_SUFFIX: &str = "scene-pending";
+/// The suffix of the files moved aside while a scene is switched to.
+const BACKUP_SUFFIX: &str = "scene-backup";
+
+/// Scenes may be switched to from the scheduler while clients change the files a switch
+/// replaces, so both hold this lock.
+static SWITCH_LOCK: Mutex<()> = Mutex::new(());
+
+/// Takes the lock held while a scene is switched to. Everything writing a plugin's meta,
+/// its instances, the style or the scenes holds it too, so a switch can't move its files
+/// over their changes.
+pub(crate) fn lock_switch() -> MutexGuard<'static, ()> {
+    SWITCH_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The config values of a plugin in a scene.
+#[derive(Clone, Serialize, Deserialize)]
+struct ScenePlugin {
+    name: PluginName,
+    configs: Vec<ConfigValue>,
+    /// The additional instances of the plugin, or none to leave them as they are.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instances: Option<Vec<Instance>>,
+}
+
+/// A snapshot of which plugins are enabled, their config values and the style.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Scene {
+    name: String,
+    plugins: Vec<ScenePlugin>,
+    /// The saved stylesheet, or none to remove the style.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style: Option<String>,
+    /// The times of day the scene is switched to, as `HH:MM`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    schedule: Vec<String>,
+}
+
+impl Scene {
+    /// Returns the scene as listed to clients, without the stylesheet and with the
+    /// values of secrets masked.
+    fn summary(&self) -> Value {
+        let plugins: Vec<ScenePlugin> = self
+            .plugins
+            .iter()
+            .cloned()
+            .map(|mut p| {
+                let instance_configs = p.instances.iter_mut().flatten().map(|i| &mut i.configs);
+                for cv in std::iter::once(&mut p.configs)
+                    .chain(instance_configs)
+                    .flatten()
+                    .filter(|cv| is_encrypted(&cv.value))
+                {
+                    cv.value = Value::String(MASK.to_string());
+                }
+                p
+            })
+            .collect();
+
+        json!({
+            "name": self.name,
+            "plugins": plugins,
+            "style": self.style.is_some(),
+            "schedule": self.schedule,
+        })
+    }
+}
+
+fn read_scenes() -> Vec<Scene> {
+    let raw = match data_path("scenes.json").and_then(|p| fs::read_to_string(p).ok()) {
+        Some(r) => r,
+        None => return vec![],
+    };
+
+    serde_json::from_str(&raw).unwrap_or_else(|_| {
+        warn!("Failed to parse scenes.json.");
+        vec![]
+    })
+}
+
+fn write_scenes(scenes: &[Scene]) -> std::result::Result<(), String> {
+    let path = data_path("scenes.json").ok_or("Invalid scenes path.")?;
+
+    fs::write(path, json!(scenes).to_string()).map_err(|_| "Failed to write scenes.".to_string())
+}
+
+/// Parses the `name` of the scene of a message.
+fn parse_name(data: &Value) -> Option<String> {
+    let name = data["name"].as_str()?.trim();
+
+    let valid = !name.is_empty() && name.chars().count() <= MAX_SCENE_NAME_LENGTH;
+    valid.then(|| name.to_string())
+}
+
+/// Removes the values of configs of a plugin from every scene, such as secrets that
+/// weren't carried over on reinstall.
+pub fn forget_scene_values(plugin: &PluginName, names: &[String]) {
+    let mut scenes = read_scenes();
+    let mut changed = false;
+    for scene_plugin in scenes
+        .iter_mut()
+        .flat_map(|s| s.plugins.iter_mut())
+        .filter(|p| &p.name == plugin)
+    {
+        let instance_configs = scene_plugin
+            .instances
+            .iter_mut()
+            .flatten()
+            .map(|i| &mut i.configs);
+        for configs in std::iter::once(&mut scene_plugin.configs).chain(instance_configs) {
+            let count = configs.len();
+            configs.retain(|cv| !names.contains(&cv.name));
+            changed |= configs.len() != count;
+        }
+    }
+
+    if changed {
+        if let Err(e) = write_scenes(&scenes) {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Returns the values saved for a plugin in every scene.
+pub fn scene_values(plugin: &PluginName) -> Vec<ConfigValue> {
+    read_scenes()
+        .into_iter()
+        .flat_map(|s| s.plugins)
+        .filter(|p| &p.name == plugin)
+        .flat_map(|p| {
+            let instance_values = p.instances.into_iter().flatten().flat_map(|i| i.configs);
+            p.configs.into_iter().chain(instance_values)
+        })
+        .collect()
+}
+
+/// Returns `path` with a suffix, such as the path a file is written to before it
+/// replaces `path`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut suffixed = path.as_os_str().to_owned();
+    suffixed.push(format!(".{}", suffix));
+
+    PathBuf::from(suffixed)
+}
+
+/// Moves the pending files into place. The files they replace are moved aside first, so
+/// if any move fails, the moves already made are undone and nothing changes.
+///
+/// # Parameters
+///
+/// * `pending` - The pending files and the paths they replace.
+fn move_into_place(pending: &[(PathBuf, PathBuf)]) -> std::result::Result<(), String> {
+    let mut moved: Vec<(&PathBuf, Option<PathBuf>)> = Vec::new();
+    let mut failed = false;
+
+    for (pending_path, path) in pending {
+        let backup = path.exists().then(|| with_suffix(path, BACKUP_SUFFIX));
+        if backup
+            .as_ref()
+            .is_some_and(|b| fs::rename(path, b).is_err())
+        {
+            failed = true;
+            break;
+        }
+        if fs::rename(pending_path, path).is_err() {
+            if let Some(backup) = backup {
+                let _ = fs::rename(backup, path);
+            }
+            failed = true;
+            break;
+        }
+        moved.push((path, backup));
+    }
+
+    if !failed {
+        for backup in moved.into_iter().filter_map(|(_, b)| b) {
+            let _ = fs::remove_file(backup);
+        }
+        return Ok(());
+    }
+
+    for (path, backup) in moved.into_iter().rev() {
+        let restored = match backup {
+            Some(backup) => fs::rename(backup, path),
+            None => fs::remove_file(path),
+        };
+        if restored.is_err() {
+            warn!("Failed to restore {}.", path.display());
+        }
+    }
+    for (pending_path, _) in pending {
+        let _ = fs::remove_file(pending_path);
+    }
+
+    Err("Failed to switch scene.".to_string())
+}
+
+/// Captures the current state of every plugin and the style as a scene.
+fn capture(name: String, schedule: Vec<String>) -> Scene {
+    let plugins = installed_plugins()
+        .into_iter()
+        .map(|meta| ScenePlugin {
+            instances: Some(read_instances(&meta.name)),
+            name: meta.name,
+            configs: meta
+                .configs
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|c| {
+                    c.value.map(|value| ConfigValue {
+                        name: c.name,
+                        value,
+                    })
+                })
+                .collect(),
+        })
+        .collect();
+
+    Scene {
+        name,
+        plugins,
+        style: data_path("style.css").and_then(|p| fs::read_to_string(p).ok()),
+        schedule,
+    }
+}
+
+/// Disables an instance of a plugin.
+fn disable_instance(instance: &mut Instance) {
+    instance.configs.retain(|cv| cv.name != "enabled");
+    instance.configs.push(ConfigValue {
+        name: "enabled".to_string(),
+        value: Value::Bool(false),
+    });
+}
+
+/// Switches to a scene. Every new `meta.json`, instances file and `style.css` is
+/// checked and written next to the current one first, and only once all of them are
+/// written are they moved into place, so a failure leaves the current state untouched.
+/// Plugins installed after the scene was saved are disabled, along with their
+/// instances.
+///
+/// # Returns
+///
+/// * `Result<Message, String>` - The `applyScene` message for displays, or the reason
+///   it failed.
+pub fn switch_scene(name: &str) -> std::result::Result<Message, String> {
+    let _guard = lock_switch();
+
+    let scene = read_scenes()
+        .into_iter()
+        .find(|s| s.name == name)
+        .ok_or("Scene not found.")?;
+
+    let mut metas: Vec<(PathBuf, PluginMeta, Vec<Instance>)> = Vec::new();
+    for mut meta in installed_plugins() {
+        let dir = plugin_dir(&meta.name).ok_or("Invalid plugin directory.")?;
+        let mut configs = meta.configs.clone().unwrap_or_default();
+        let mut instances = read_instances(&meta.name);
+
+        let values: Vec<ConfigValue> = match scene.plugins.iter().find(|p| p.name == meta.name) {
+            Some(plugin) => {
+                if let Some(scene_instances) = &plugin.instances {
+                    instances = scene_instances.clone();
+                }
+
+                plugin
+                    .configs
+                    .iter()
+                    .filter(|v| configs.iter().any(|c| c.name == v.name))
+                    .cloned()
+                    .collect()
+            }
+            None => {
+                instances.iter_mut().for_each(disable_instance);
+
+                vec![ConfigValue {
+                    name: "enabled".to_string(),
+                    value: Value::Bool(false),
+                }]
+            }
+        };
+
+        if let Err(errors) = apply_values(&meta.name, &mut configs, &values) {
+            return Err(format!(
+                "The scene doesn't fit {}: {}",
+                meta.name, errors[0].message
+            ));
+        }
+
+        meta.configs = Some(configs);
+        metas.push((dir, meta, instances));
+    }
+
+    let style_path = data_path("style.css").ok_or("Invalid style path.")?;
+    let mut pending: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut write_pending = |path: PathBuf, contents: &[u8]| {
+        let pending_path = with_suffix(&path, PENDING_SUFFIX);
+        let result = fs::write(&pending_path, contents);
+        pending.push((pending_path, path));
+        result
+    };
+
+    let mut written = metas.iter().try_for_each(|(dir, meta, instances)| {
+        write_pending(dir.join("meta.json"), json!(meta).to_string().as_bytes())?;
+
+        match instances_path(&meta.name) {
+            Some(path) if !instances.is_empty() || path.exists() => {
+                fs::create_dir_all(path.parent().unwrap())?;
+                write_pending(path, json!(instances).to_string().as_bytes())
+            }
+            _ => Ok(()),
+        }
+    });
+    if let (Ok(_), Some(style)) = (&written, &scene.style) {
+        written = write_pending(style_path.clone(), style.as_bytes());
+    }
+
+    if written.is_err() {
+        for (pending_path, _) in pending {
+            let _ = fs::remove_file(pending_path);
+        }
+        return Err("Failed to write scene.".to_string());
+    }
+
+    move_into_place(&pending)?;
+
+    match &scene.style {
+        Some(style) => {
+            remove_siblings(&style_path);
+            record_style(style.as_bytes())?;
+        }
+        None if style_path.exists() => {
+            let _ = fs::remove_file(&style_path);
+            remove_siblings(&style_path);
+            if let Some(meta_path) = data_path("style.json") {
+                let _ = fs::remove_file(meta_path);
+            }
+        }
+        None => {}
+    }
+
+    for (dir, meta, _) in metas.iter() {
+        prune_uploads(dir, &meta.name, meta.configs.as_deref().unwrap_or_default());
+    }
+
+    info!("Switched to scene {}.", scene.name);
+
+    let plugins: Vec<Value> = metas
+        .into_iter()
+        .flat_map(|(_, meta, _)| client_entries(meta))
+        .collect();
+
+    Ok(Message {
+        type_: MessageType::ApplyScene,
+        data: json!({
+            "name": scene.name,
+            "plugins": plugins,
+            "style": saved_style(),
+        }),
+    })
+}
+
+/// Returns a `listScenes` message with every scene.
+fn scenes_message() -> Message {
+    let scenes: Vec<Value> = read_scenes().iter().map(Scene::summary).collect();
+
+    Message {
+        type_: MessageType::ListScenes,
+        data: json!(scenes),
+    }
+}
+
+/// Switches to scenes at the times of day in their schedule, in local time.
+pub fn spawn_scheduler(broadcaster: Sender) {
+    thread::spawn(move || loop {
+        // Wake up at the start of every minute.
+        let now = Local::now();
+        thread::sleep(Duration::from_secs(60 - now.second() as u64));
+
+        let time = Local::now().format("%H:%M").to_string();
+        let due = read_scenes()
+            .into_iter()
+            .filter(|s| s.schedule.contains(&time));
+
+        for scene in due {
+            match switch_scene(&scene.name) {
+                Ok(message) => {
+                    let _ = broadcaster
+                        .broadcast(WSMessage::Text(serde_json::to_string(&message).unwrap()));
+                }
+                Err(e) => warn!("Failed to switch to scene {}: {}", scene.name, e),
+            }
+        }
+    });
+}
+
+impl Server {
+    /// Lists the scenes.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn list_scenes(&self) -> Result<()> {
+        self.send(scenes_message())
+    }
+
+    /// Saves the current state of every plugin and the style as a scene, replacing any
+    /// scene with the same name.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the scene, and optionally a
+    ///   `schedule`, an array of times of day such as `"22:30"` to switch to it at.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn save_scene(&self, data: Value) -> Result<()> {
+        let name = match parse_name(&data) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get scene.")),
+        };
+
+        let mut schedule = Vec::new();
+        for time in data["schedule"].as_array().into_iter().flatten() {
+            match time.as_str().and_then(parse_time).filter(|t| t.len() == 5) {
+                Some(t) if !schedule.contains(&t) => schedule.push(t),
+                Some(_) => {}
+                None => return self.send(Message::error("Invalid schedule.")),
+            }
+        }
+
+        let _guard = lock_switch();
+        let mut scenes = read_scenes();
+        let scene = capture(name, schedule);
+        match scenes.iter_mut().find(|s| s.name == scene.name) {
+            Some(existing) => *existing = scene,
+            None => scenes.push(scene),
+        }
+
+        if let Err(e) = write_scenes(&scenes) {
+            return self.send(Message::error(&e));
+        }
+
+        self.broadcast(scenes_message())
+    }
+
+    /// Switches to a scene and broadcasts every plugin and the style in one
+    /// `applyScene` message.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the scene.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn apply_scene(&self, data: Value) -> Result<()> {
+        let name = match parse_name(&data) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get scene.")),
+        };
+
+        match switch_scene(&name) {
+            Ok(message) => self.broadcast(message),
+            Err(e) => self.send(Message::error(&e)),
+        }
+    }
+
+    /// Switches to a scene from an HTTP request, such as `POST /scenes/Night`, so that
+    /// scenes can be switched by remote commands without a WebSocket.
+    ///
+    /// # Parameters
+    ///
+    /// * `req` - The HTTP request.
+    /// * `name` - The percent-encoded name of the scene.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response>` - The HTTP response.
+    pub fn trigger_scene(&self, req: &Request, name: &str) -> Result<Response> {
+        if req.method() != "POST" {
+            return Ok(Response::new(
+                405,
+                "Method Not Allowed",
+                b"405 - Method Not Allowed".to_vec(),
+            ));
+        }
+
+        let name = match percent_decode(name) {
+            Some(n) => n,
+            None => {
+                return Ok(Response::new(
+                    400,
+                    "Bad Request",
+                    b"400 - Bad Request".to_vec(),
+                ))
+            }
+        };
+
+        match switch_scene(&name) {
+            Ok(message) => {
+                self.broadcast(message)?;
+                Ok(Response::new(200, "OK", b"OK".to_vec()))
+            }
+            Err(e) => {
+                let status = match read_scenes().iter().any(|s| s.name == name) {
+                    true => (500, "Internal Server Error"),
+                    false => (404, "Not Found"),
+                };
+                Ok(Response::new(status.0, status.1, e.into_bytes()))
+            }
+        }
+    }
+
+    /// Deletes a scene.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the scene.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn delete_scene(&self, data: Value) -> Result<()> {
+        let name = match parse_name(&data) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get scene.")),
+        };
+
+        let _guard = lock_switch();
+        let mut scenes = read_scenes();
+        let count = scenes.len();
+        scenes.retain(|s| s.name != name);
+
+        if scenes.len() == count {
+            return self.send(Message::error("Scene not found."));
+        }
+
+        if let Err(e) = write_scenes(&scenes) {
+            return self.send(Message::error(&e));
+        }
+
+        self.broadcast(scenes_message())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("widgetbox-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn moves_pending_files_into_place() {
+        let root = temp_root("scene-move");
+        let (a, b) = (root.join("a.json"), root.join("b.json"));
+        fs::write(&a, "old").unwrap();
+        fs::write(with_suffix(&a, PENDING_SUFFIX), "new a").unwrap();
+        fs::write(with_suffix(&b, PENDING_SUFFIX), "new b").unwrap();
+
+        let pending = [
+            (with_suffix(&a, PENDING_SUFFIX), a.clone()),
+            (with_suffix(&b, PENDING_SUFFIX), b.clone()),
+        ];
+        assert!(move_into_place(&pending).is_ok());
+
+        assert_eq!(fs::read_to_string(&a).unwrap(), "new a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "new b");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn rolls_back_when_a_move_fails() {
+        let root = temp_root("scene-rollback");
+        let (a, b, c) = (
+            root.join("a.json"),
+            root.join("b.json"),
+            root.join("c.json"),
+        );
+        fs::write(&a, "old a").unwrap();
+        fs::write(&c, "old c").unwrap();
+        fs::write(with_suffix(&a, PENDING_SUFFIX), "new a").unwrap();
+        fs::write(with_suffix(&b, PENDING_SUFFIX), "new b").unwrap();
+
+        // The pending file of `c` is missing, so moving it fails.
+        let pending = [
+            (with_suffix(&a, PENDING_SUFFIX), a.clone()),
+            (with_suffix(&b, PENDING_SUFFIX), b.clone()),
+            (with_suffix(&c, PENDING_SUFFIX), c.clone()),
+        ];
+        assert!(move_into_place(&pending).is_err());
+
+        assert_eq!(fs::read_to_string(&a).unwrap(), "old a");
+        assert!(!b.exists());
+        assert_eq!(fs::read_to_string(&c).unwrap(), "old c");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,255 @@
+use serde_json::{Map, Value};
+
+/// The deepest nesting of schemas and values checked.
+const MAX_DEPTH: usize = 32;
+
+fn describe(path: &str) -> String {
+    match path {
+        "" => "The value".to_string(),
+        path => format!("\"{}\"", path),
+    }
+}
+
+fn type_matches(expected: &str, value: &Value) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => false,
+    }
+}
+
+fn check_object(
+    schema: &Value,
+    object: &Map<String, Value>,
+    path: &str,
+    depth: usize,
+) -> Result<Value, String> {
+    let mut normalized = Map::new();
+    let properties = schema["properties"].as_object();
+
+    for (key, value) in object {
+        let child_path = match path {
+            "" => key.clone(),
+            path => format!("{}.{}", path, key),
+        };
+
+        match properties.and_then(|p| p.get(key)) {
+            Some(property) => {
+                normalized.insert(key.clone(), check_at(property, value, &child_path, depth)?);
+            }
+            None if schema["additionalProperties"] == Value::Bool(false) => {
+                return Err(format!("{} is not allowed.", describe(&child_path)));
+            }
+            None => {
+                normalized.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    // Fill in the defaults of missing properties.
+    for (key, property) in properties.into_iter().flatten() {
+        if let (false, Some(default)) = (normalized.contains_key(key), property.get("default")) {
+            normalized.insert(key.clone(), default.clone());
+        }
+    }
+
+    for key in schema["required"].as_array().into_iter().flatten() {
+        if let Some(key) = key.as_str() {
+            if !normalized.contains_key(key) {
+                let child_path = match path {
+                    "" => key.to_string(),
+                    path => format!("{}.{}", path, key),
+                };
+                return Err(format!("{} is required.", describe(&child_path)));
+            }
+        }
+    }
+
+    Ok(Value::Object(normalized))
+}
+
+fn check_at(schema: &Value, value: &Value, path: &str, depth: usize) -> Result<Value, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("{} is nested too deeply.", describe(path)));
+    }
+    let depth = depth + 1;
+
+    let valid_type = match &schema["type"] {
+        Value::String(t) => type_matches(t, value),
+        Value::Array(types) => types
+            .iter()
+            .any(|t| t.as_str().is_some_and(|t| type_matches(t, value))),
+        _ => true,
+    };
+    if !valid_type {
+        return Err(format!(
+            "{} must be of type {}.",
+            describe(path),
+            schema["type"]
+        ));
+    }
+
+    if let Some(options) = schema["enum"].as_array() {
+        if !options.contains(value) {
+            return Err(format!(
+                "{} must be one of {}.",
+                describe(path),
+                schema["enum"]
+            ));
+        }
+    }
+
+    if let Some(number) = value.as_f64() {
+        if schema["minimum"].as_f64().is_some_and(|min| number < min) {
+            return Err(format!(
+                "{} must be at least {}.",
+                describe(path),
+                schema["minimum"]
+            ));
+        }
+        if schema["maximum"].as_f64().is_some_and(|max| number > max) {
+            return Err(format!(
+                "{} must be at most {}.",
+                describe(path),
+                schema["maximum"]
+            ));
+        }
+    }
+
+    if let Some(string) = value.as_str() {
+        let length = string.chars().count() as u64;
+        if schema["minLength"].as_u64().is_some_and(|min| length < min) {
+            return Err(format!(
+                "{} must be at least {} characters long.",
+                describe(path),
+                schema["minLength"]
+            ));
+        }
+        if schema["maxLength"].as_u64().is_some_and(|max| length > max) {
+            return Err(format!(
+                "{} must be at most {} characters long.",
+                describe(path),
+                schema["maxLength"]
+            ));
+        }
+    }
+
+    match value {
+        Value::Object(object) => check_object(schema, object, path, depth),
+        Value::Array(items) => {
+            let count = items.len() as u64;
+            if schema["minItems"].as_u64().is_some_and(|min| count < min) {
+                return Err(format!(
+                    "{} must have at least {} items.",
+                    describe(path),
+                    schema["minItems"]
+                ));
+            }
+            if schema["maxItems"].as_u64().is_some_and(|max| count > max) {
+                return Err(format!(
+                    "{} must have at most {} items.",
+                    describe(path),
+                    schema["maxItems"]
+                ));
+            }
+
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| match schema.get("items") {
+                    Some(item_schema) => {
+                        check_at(item_schema, item, &format!("{}[{}]", path, i), depth)
+                    }
+                    None => Ok(item.clone()),
+                })
+                .collect::<Result<Vec<Value>, String>>()
+                .map(Value::Array)
+        }
+        _ => Ok(value.clone()),
+    }
+}
+
+/// Checks a value against a JSON Schema and fills in the defaults of missing
+/// properties. Only a subset is supported: `type`, `enum`, `properties`, `required`,
+/// `additionalProperties: false`, `items`, `minimum`, `maximum`, `minLength`,
+/// `maxLength`, `minItems`, `maxItems` and `default`.
+///
+/// # Returns
+///
+/// * `Result<Value, String>` - The value with defaults filled in, or why it is invalid.
+pub fn check(schema: &Value, value: &Value) -> Result<Value, String> {
+    check_at(schema, value, "", 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "city": { "type": "string", "minLength": 1, "maxLength": 20 },
+                "days": { "type": "integer", "minimum": 1, "maximum": 7, "default": 3 },
+                "units": { "enum": ["metric", "imperial"] },
+                "tags": { "type": "array", "items": { "type": "string" }, "maxItems": 2 }
+            },
+            "required": ["city"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        assert_eq!(
+            check(&schema(), &json!({ "city": "Oslo" })),
+            Ok(json!({ "city": "Oslo", "days": 3 }))
+        );
+    }
+
+    #[test]
+    fn accepts_valid_values() {
+        let value = json!({ "city": "Oslo", "days": 7, "units": "metric", "tags": ["a"] });
+        assert_eq!(check(&schema(), &value), Ok(value));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for value in [
+            json!("Oslo"),
+            json!({}),
+            json!({ "city": "" }),
+            json!({ "city": "Oslo", "days": 0 }),
+            json!({ "city": "Oslo", "days": 1.5 }),
+            json!({ "city": "Oslo", "units": "kelvin" }),
+            json!({ "city": "Oslo", "tags": ["a", "b", "c"] }),
+            json!({ "city": "Oslo", "tags": [1] }),
+            json!({ "city": "Oslo", "extra": true }),
+        ] {
+            assert!(check(&schema(), &value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn names_the_invalid_property() {
+        let error = check(&schema(), &json!({ "city": "Oslo", "tags": ["a", 2] })).unwrap_err();
+        assert!(error.contains("\"tags[1]\""), "{}", error);
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let (mut schema, mut value) = (json!({}), json!(0));
+        for _ in 0..=MAX_DEPTH {
+            schema = json!({ "items": schema });
+            value = json!([value]);
+        }
+
+        assert!(check(&schema, &value).is_err());
+    }
+}
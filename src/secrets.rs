@@ -0,0 +1,140 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde_json::Value;
+
+use crate::paths::{data_path, PluginName};
+use crate::plugin::Config;
+
+/// The file in the data directory holding the key secrets are encrypted with.
+const KEY_FILE: &str = "secret.key";
+/// Marks a config value as encrypted, followed by the base64 encoded nonce and
+/// ciphertext.
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_SIZE: usize = 12;
+/// Sent to clients instead of the value of a secret that is set. Sending it back in a
+/// `configPlugin` message keeps the current value.
+pub const MASK: &str = "********";
+
+/// Reads the key secrets are encrypted with, creating it on first use.
+fn load_key() -> Result<Key, String> {
+    let path = data_path(KEY_FILE).ok_or("Invalid secret key path.")?;
+
+    if let Ok(raw) = fs::read(&path) {
+        return match raw.len() == 32 {
+            true => Ok(*Key::from_slice(&raw)),
+            false => Err("The secret key is corrupted.".to_string()),
+        };
+    }
+
+    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| options.open(&path))
+        .and_then(|mut file| file.write_all(&key))
+        .map_err(|_| "Failed to create secret key.")?;
+
+    Ok(key)
+}
+
+/// Whether a config value is an encrypted secret.
+pub fn is_encrypted(value: &Value) -> bool {
+    value
+        .as_str()
+        .is_some_and(|s| s.starts_with(ENCRYPTED_PREFIX))
+}
+
+/// The associated data a secret is encrypted with, which ties its value to one config
+/// of one plugin, so it can't be decrypted as the value of another.
+fn associated_data(plugin: &PluginName, config: &str) -> String {
+    format!("{}/{}", plugin, config)
+}
+
+/// Encrypts the value of a secret config to be saved in the plugin's meta.
+///
+/// # Parameters
+///
+/// * `value` - The value of the secret.
+/// * `plugin` - The plugin declaring the secret.
+/// * `config` - The name of the secret config.
+pub fn encrypt(value: &Value, plugin: &PluginName, config: &str) -> Result<Value, String> {
+    let cipher = ChaCha20Poly1305::new(&load_key()?);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let (msg, aad) = (value.to_string(), associated_data(plugin, config));
+    let payload = Payload {
+        msg: msg.as_bytes(),
+        aad: aad.as_bytes(),
+    };
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| "Failed to encrypt secret.")?,
+    );
+
+    Ok(Value::String(format!(
+        "{}{}",
+        ENCRYPTED_PREFIX,
+        STANDARD.encode(sealed)
+    )))
+}
+
+/// Decrypts the saved value of a secret config.
+///
+/// # Parameters
+///
+/// * `value` - The encrypted value.
+/// * `plugin` - The plugin declaring the secret.
+/// * `config` - The name of the secret config.
+///
+/// # Returns
+///
+/// * `Result<Value, String>` - The value, or why it can't be decrypted, such as the key
+///   having changed since it was saved, or the value belonging to another config.
+pub fn decrypt(value: &Value, plugin: &PluginName, config: &str) -> Result<Value, String> {
+    let sealed = value
+        .as_str()
+        .and_then(|s| s.strip_prefix(ENCRYPTED_PREFIX))
+        .and_then(|s| STANDARD.decode(s).ok())
+        .filter(|s| s.len() > NONCE_SIZE)
+        .ok_or("The secret is not encrypted.")?;
+
+    let cipher = ChaCha20Poly1305::new(&load_key()?);
+    let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+    let aad = associated_data(plugin, config);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: aad.as_bytes(),
+    };
+    let plain = cipher
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| "Failed to decrypt secret.")?;
+
+    serde_json::from_slice(&plain).map_err(|_| "Failed to parse secret.".to_string())
+}
+
+/// Replaces the values of secret configs with [`MASK`], before they are sent to clients.
+pub fn mask_secrets<'a>(configs: impl Iterator<Item = &'a mut Config>) {
+    for config in configs.filter(|c| c.type_ == "secret") {
+        if config
+            .value
+            .as_ref()
+            .is_some_and(|v| !v.is_null() && v != "")
+        {
+            config.value = Some(Value::String(MASK.to_string()));
+        }
+    }
+}
@@ -0,0 +1,537 @@
+#[cfg(feature = "embed-frontend")]
+use std::path::Component;
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::error;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use ws::{Request, Response, Result};
+
+use crate::compress::{accepted_encodings, compress, is_compressible, Encoding};
+use crate::paths::{confine, data_path, plugin_dir, PluginName};
+
+/// Guesses the MIME type of a file from its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "ics" => "text/calendar; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Decodes `%XX` escapes in a request path.
+pub fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Resolves a file inside `folder`, refusing anything that escapes it.
+fn resolve_file(folder: &Path, filename: &str) -> Option<PathBuf> {
+    if !folder.is_dir() {
+        return None;
+    }
+
+    let file_path = confine(folder, Path::new(filename))?;
+    file_path.is_file().then_some(file_path)
+}
+
+/// Whether a file is the meta of a plugin or a compressed copy of it. The meta holds the
+/// encrypted values of secrets, and is only read by the server.
+fn is_meta(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n == "meta.json" || n.starts_with("meta.json."))
+}
+
+/// Resolves a file inside a plugin directory that may be served, which is any file but
+/// the meta, however the request spells its path.
+fn resolve_plugin_file(dir: &Path, filename: &str) -> Option<PathBuf> {
+    resolve_file(dir, filename).filter(|p| !is_meta(p))
+}
+
+/// How long clients may reuse a response without asking again.
+#[derive(Clone, Copy)]
+pub enum CachePolicy {
+    /// The URL changes whenever the content does, so it can be cached forever.
+    Immutable,
+    /// The content may change under the same URL and must be revalidated.
+    Revalidate,
+}
+
+impl CachePolicy {
+    fn header(&self) -> &'static str {
+        match self {
+            CachePolicy::Immutable => "public, max-age=31536000, immutable",
+            CachePolicy::Revalidate => "no-cache",
+        }
+    }
+}
+
+/// The number of hex digits of the content hash used as a file revision.
+pub const REVISION_LENGTH: usize = 16;
+
+/// A content hash along with the modification time and length it was computed for.
+type HashEntry = (SystemTime, u64, String);
+
+static HASHES: LazyLock<Mutex<HashMap<PathBuf, HashEntry>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Looks up a content hash, only computing it when the modification time or length
+/// changed since it was last computed.
+fn cached_hash(
+    key: &Path,
+    modified: SystemTime,
+    len: u64,
+    read: impl FnOnce() -> Option<Vec<u8>>,
+) -> Option<String> {
+    let mut hashes = HASHES.lock().unwrap();
+    if let Some((m, l, hash)) = hashes.get(key) {
+        if *m == modified && *l == len {
+            return Some(hash.clone());
+        }
+    }
+
+    let hash = sha256_hex(&read()?);
+    hashes.insert(key.to_path_buf(), (modified, len, hash.clone()));
+
+    Some(hash)
+}
+
+/// Returns the SHA-256 hash of a file, only rereading it when it has changed.
+pub fn content_hash(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+
+    cached_hash(path, metadata.modified().ok()?, metadata.len(), || {
+        fs::read(path).ok()
+    })
+}
+
+/// The directory the frontends are read from. Files found there take precedence over
+/// the ones embedded into the binary.
+fn static_dir() -> PathBuf {
+    PathBuf::from(env::var("WIDGETBOX_STATIC_DIR").unwrap_or_else(|_| "static".to_string()))
+}
+
+#[cfg(feature = "embed-frontend")]
+static FRONTEND: include_dir::Dir<'static> =
+    include_dir::include_dir!("$CARGO_MANIFEST_DIR/static");
+
+/// Where the contents of a served file come from.
+enum Source {
+    Disk(PathBuf),
+    #[cfg(feature = "embed-frontend")]
+    Embedded(&'static include_dir::File<'static>),
+}
+
+impl Source {
+    /// A path identifying the file in the caches.
+    fn key(&self) -> PathBuf {
+        match self {
+            Source::Disk(path) => path.clone(),
+            #[cfg(feature = "embed-frontend")]
+            Source::Embedded(file) => Path::new("embedded").join(file.path()),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Source::Disk(path) => path,
+            #[cfg(feature = "embed-frontend")]
+            Source::Embedded(file) => file.path(),
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        match self {
+            Source::Disk(path) => fs::metadata(path).and_then(|m| m.modified()).ok(),
+            #[cfg(feature = "embed-frontend")]
+            Source::Embedded(_) => None,
+        }
+    }
+
+    fn hash(&self) -> Option<String> {
+        match self {
+            Source::Disk(path) => content_hash(path),
+            #[cfg(feature = "embed-frontend")]
+            Source::Embedded(file) => cached_hash(
+                &self.key(),
+                UNIX_EPOCH,
+                file.contents().len() as u64,
+                || Some(file.contents().to_vec()),
+            ),
+        }
+    }
+
+    fn read(&self) -> Option<Vec<u8>> {
+        match self {
+            Source::Disk(path) => fs::read(path).ok(),
+            #[cfg(feature = "embed-frontend")]
+            Source::Embedded(file) => Some(file.contents().to_vec()),
+        }
+    }
+
+    /// Returns the precompressed sibling of the file, if it exists and is up to date.
+    fn sibling(&self, encoding: Encoding) -> Option<Source> {
+        match self {
+            Source::Disk(path) => {
+                let sibling = encoding.sibling(path);
+                let modified = fs::metadata(&sibling).and_then(|m| m.modified()).ok()?;
+
+                (Some(modified) >= self.modified()).then_some(Source::Disk(sibling))
+            }
+            #[cfg(feature = "embed-frontend")]
+            Source::Embedded(file) => FRONTEND
+                .get_file(encoding.sibling(file.path()))
+                .map(Source::Embedded),
+        }
+    }
+}
+
+/// Resolves a file of one of the frontends, `display` or `editor`.
+fn frontend_source(app: &str, filename: &str) -> Option<Source> {
+    if let Some(path) = resolve_file(&static_dir().join(app), filename) {
+        return Some(Source::Disk(path));
+    }
+
+    #[cfg(feature = "embed-frontend")]
+    if Path::new(filename)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        if let Some(file) = FRONTEND.get_file(Path::new(app).join(filename)) {
+            return Some(Source::Embedded(file));
+        }
+    }
+
+    None
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn header_str<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.header(name).and_then(|v| std::str::from_utf8(v).ok())
+}
+
+/// Checks the conditional request headers against the current validators.
+fn is_not_modified(req: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = header_str(req, "if-none-match") {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+
+    let since =
+        header_str(req, "if-modified-since").and_then(|s| httpdate::parse_http_date(s).ok());
+    match (since, modified) {
+        // HTTP dates only have a resolution of one second.
+        (Some(since), Some(modified)) => seconds(modified) <= seconds(since),
+        _ => false,
+    }
+}
+
+/// Compressed contents of a file along with the content hash they were compressed from.
+type CompressedEntry = (String, Vec<u8>);
+
+/// The most bytes of compressed contents kept in memory.
+const MAX_COMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Compressed contents of files without a precompressed sibling. Once it holds more
+/// than `MAX_COMPRESSED_SIZE` bytes, it is emptied.
+static COMPRESSED: LazyLock<Mutex<HashMap<PathBuf, CompressedEntry>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Picks how to encode a file for the client. A fresh precompressed sibling is
+/// preferred, otherwise gzip is done on the fly.
+///
+/// # Returns
+///
+/// * `Option<(Encoding, Option<Source>)>` - The encoding and the sibling to read it
+///   from, or `None` to send the file as is.
+fn pick_encoding(req: &Request, source: &Source) -> Option<(Encoding, Option<Source>)> {
+    let accepted = accepted_encodings(header_str(req, "accept-encoding")?);
+
+    for encoding in accepted.iter() {
+        if let Some(sibling) = source.sibling(*encoding) {
+            return Some((*encoding, Some(sibling)));
+        }
+    }
+
+    accepted
+        .contains(&Encoding::Gzip)
+        .then_some((Encoding::Gzip, None))
+}
+
+fn compress_cached(source: &Source, hash: &str, encoding: Encoding) -> Option<Vec<u8>> {
+    let key = source.key();
+
+    let mut compressed = COMPRESSED.lock().unwrap();
+    if let Some((h, bytes)) = compressed.get(&key) {
+        if h == hash {
+            return Some(bytes.clone());
+        }
+    }
+
+    let bytes = compress(&source.read()?, encoding)?;
+    let size: usize = compressed.values().map(|(_, b)| b.len()).sum();
+    if size + bytes.len() > MAX_COMPRESSED_SIZE {
+        compressed.clear();
+    }
+    if bytes.len() <= MAX_COMPRESSED_SIZE {
+        compressed.insert(key, (hash.to_string(), bytes.clone()));
+    }
+
+    Some(bytes)
+}
+
+fn serve_source(req: &Request, source: Source, policy: CachePolicy) -> Option<Response> {
+    let modified = source.modified();
+    let hash = source.hash()?;
+    let content_type = content_type(source.path());
+
+    let compressible = is_compressible(content_type);
+    let encoding = if compressible {
+        pick_encoding(req, &source)
+    } else {
+        None
+    };
+
+    let etag = match &encoding {
+        Some((encoding, _)) => format!("\"{}-{}\"", &hash[..32], encoding.extension()),
+        None => format!("\"{}\"", &hash[..32]),
+    };
+
+    let mut response = if is_not_modified(req, &etag, modified) {
+        Response::new(304, "Not Modified", vec![])
+    } else {
+        let bytes = match &encoding {
+            Some((_, Some(sibling))) => sibling.read()?,
+            Some((encoding, None)) => compress_cached(&source, &hash, *encoding)?,
+            None => source.read()?,
+        };
+
+        let mut response = Response::new(200, "OK", bytes);
+        response
+            .headers_mut()
+            .push(("Content-Type".into(), content_type.into()));
+        if let Some((encoding, _)) = &encoding {
+            response
+                .headers_mut()
+                .push(("Content-Encoding".into(), encoding.name().into()));
+        }
+        response
+    };
+
+    let headers = response.headers_mut();
+    headers.push(("ETag".into(), etag.into()));
+    if let Some(modified) = modified {
+        headers.push((
+            "Last-Modified".into(),
+            httpdate::fmt_http_date(modified).into(),
+        ));
+    }
+    headers.push(("Cache-Control".into(), policy.header().into()));
+    if compressible {
+        headers.push(("Vary".into(), "Accept-Encoding".into()));
+    }
+    headers.push(("Access-Control-Allow-Origin".into(), "*".into()));
+    headers.push(("X-Content-Type-Options".into(), "nosniff".into()));
+
+    Some(response)
+}
+
+/// Serves a resolved file. A file requested with its current revision in the `v` query
+/// parameter never changes under that URL, so it is served as immutable.
+fn serve_file(req: &Request, file_path: PathBuf) -> Option<Response> {
+    let policy = match revision(req) {
+        Some(rev) if content_hash(&file_path).is_some_and(|h| h[..REVISION_LENGTH] == *rev) => {
+            CachePolicy::Immutable
+        }
+        _ => CachePolicy::Revalidate,
+    };
+
+    serve_source(req, Source::Disk(file_path), policy)
+}
+
+fn try_serve_frontend_file(req: &Request, app: &str, filename: &str) -> Option<Response> {
+    let policy = if is_hashed_asset(filename) {
+        CachePolicy::Immutable
+    } else {
+        CachePolicy::Revalidate
+    };
+
+    serve_source(req, frontend_source(app, filename)?, policy)
+}
+
+/// Returns the `v` query parameter of the request, if any.
+fn revision(req: &Request) -> Option<&str> {
+    let (_, query) = req.resource().split_once('?')?;
+
+    query.split('&').find_map(|pair| pair.strip_prefix("v="))
+}
+
+/// Whether a frontend asset carries a content hash in its name, as emitted by Vite.
+fn is_hashed_asset(filename: &str) -> bool {
+    let hashed_re = Regex::new(r"^assets/.+-[A-Za-z0-9_-]{8,}\.[a-z0-9]+$").unwrap();
+    hashed_re.is_match(filename)
+}
+
+pub fn try_find_plugin_or_static(req: &Request, path: &str) -> Result<Response> {
+    let not_found = Ok(Response::new(404, "Not Found", b"404 - Not Found".to_vec()));
+
+    let path = match percent_decode(path) {
+        Some(p) => p,
+        None => return not_found,
+    };
+
+    if path == "/custom/style.css" {
+        let style = data_path("style.css").filter(|p| p.is_file());
+        if let Some(response) = style.and_then(|p| serve_file(req, p)) {
+            return Ok(response);
+        }
+    }
+
+    let plugin_re = Regex::new("^/plugin/([^/]+)/(.+)$").unwrap();
+    if let Some(caps) = plugin_re.captures(&path) {
+        let response = PluginName::parse(&caps[1])
+            .and_then(|n| plugin_dir(&n))
+            .and_then(|dir| resolve_plugin_file(&dir, &caps[2]))
+            .and_then(|file_path| serve_file(req, file_path));
+        if let Some(response) = response {
+            return Ok(response);
+        }
+
+        return not_found;
+    }
+
+    let edit_re = Regex::new(r"^/edit/(.*)$").unwrap();
+    if let Some(caps) = edit_re.captures(&path) {
+        if let Some(response) = try_serve_frontend_file(req, "editor", &caps[1]) {
+            return Ok(response);
+        }
+    }
+
+    let display_re = Regex::new(r"^/(.*)$").unwrap();
+    if let Some(caps) = display_re.captures(&path) {
+        if let Some(response) = try_serve_frontend_file(req, "display", &caps[1]) {
+            return Ok(response);
+        }
+    }
+
+    not_found
+}
+
+/// Serves the entry page of one of the frontends, `display` or `editor`.
+pub fn read_html(req: &Request, app: &str) -> Response {
+    match try_serve_frontend_file(req, app, "index.html") {
+        Some(response) => response,
+        None => {
+            error!(
+                "The {} frontend is missing, run build_static.sh first.",
+                app
+            );
+            Response::new(
+                404,
+                "Not Found",
+                b"404 - Frontend not found, run build_static.sh first".to_vec(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    #[test]
+    fn never_resolves_the_meta() {
+        let dir = std::env::temp_dir().join(format!("widgetbox-serve-meta-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        for file in ["meta.json", "meta.json.gz", "lib/meta.json", "index.js"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        for filename in [
+            "meta.json",
+            "meta.json/",
+            "./meta.json",
+            "lib/../meta.json",
+            "meta.json.gz",
+            "lib/meta.json",
+            "./lib/./meta.json",
+        ] {
+            assert_eq!(resolve_plugin_file(&dir, filename), None, "{}", filename);
+        }
+        assert!(resolve_plugin_file(&dir, "index.js").is_some());
+        assert!(resolve_plugin_file(&dir, "./index.js").is_some());
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs;
+
+use crate::instances::client_entries;
+use crate::paths::data_path;
+use crate::plugin::installed_plugins;
+use crate::{Message, MessageType, Server};
+
+use log::warn;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_value, json, to_value, Value};
+use ws::Result;
+
+/// The settings a config can inherit with `inherit`.
+pub const SETTING_NAMES: [&str; 5] = ["locale", "timezone", "clock", "temperatureUnit", "location"];
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Clock {
+    #[serde(rename = "12h")]
+    TwelveHour,
+    #[serde(rename = "24h")]
+    TwentyFourHour,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Location {
+    latitude: f64,
+    longitude: f64,
+}
+
+/// Settings shared by all plugins, saved in `data/settings.json`. Unset settings are
+/// left to each plugin.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Settings {
+    /// A language tag such as `en-US`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locale: Option<String>,
+    /// An IANA time zone such as `Europe/London`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock: Option<Clock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature_unit: Option<TemperatureUnit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<Location>,
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let raw = match data_path("settings.json").and_then(|p| fs::read_to_string(p).ok()) {
+            Some(r) => r,
+            None => return Settings::default(),
+        };
+
+        match serde_json::from_str(&raw) {
+            Ok(settings) => settings,
+            Err(_) => {
+                warn!("Failed to parse settings.json, using no settings.");
+                Settings::default()
+            }
+        }
+    }
+
+    /// Returns the value of a setting, if it is set.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let value = to_value(self).ok()?.get(name)?.clone();
+
+        (!value.is_null()).then_some(value)
+    }
+
+    fn validate(&self) -> std::result::Result<(), String> {
+        let locale_re = Regex::new(r"^[A-Za-z]{2,3}(-[A-Za-z0-9]{2,8})*$").unwrap();
+        if self.locale.as_ref().is_some_and(|l| !locale_re.is_match(l)) {
+            return Err("Invalid locale.".to_string());
+        }
+
+        let timezone_re = Regex::new(r"^[A-Za-z]+([/_+-][A-Za-z0-9]+)*$").unwrap();
+        if self
+            .timezone
+            .as_ref()
+            .is_some_and(|t| !timezone_re.is_match(t))
+        {
+            return Err("Invalid timezone.".to_string());
+        }
+
+        if let Some(location) = &self.location {
+            if !(-90.0..=90.0).contains(&location.latitude)
+                || !(-180.0..=180.0).contains(&location.longitude)
+            {
+                return Err("Invalid location.".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn save(&self) -> std::result::Result<(), String> {
+        let path = data_path("settings.json").ok_or("Invalid settings path.")?;
+
+        fs::write(path, json!(self).to_string())
+            .map_err(|_| "Failed to write settings.".to_string())
+    }
+}
+
+impl Server {
+    /// Sends the global settings.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn get_settings(&self) -> Result<()> {
+        self.send(Message {
+            type_: MessageType::GetSettings,
+            data: json!(Settings::load()),
+        })
+    }
+
+    /// Replaces the global settings, then broadcasts them along with every instance of
+    /// the plugins that inherit one of them, so displays pick up the new effective values.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the settings. Settings left out are unset.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn set_settings(&self, data: Value) -> Result<()> {
+        let settings = match from_value::<Settings>(data) {
+            Ok(s) => s,
+            Err(_) => return self.send(Message::error("Failed to parse settings.")),
+        };
+
+        if let Err(e) = settings.validate().and_then(|_| settings.save()) {
+            return self.send(Message::error(&e));
+        }
+
+        self.broadcast(Message {
+            type_: MessageType::SetSettings,
+            data: json!(settings),
+        })?;
+
+        for plugin in installed_plugins() {
+            let inherits = plugin.configs.iter().flatten().any(|c| c.inherit.is_some());
+
+            if !inherits {
+                continue;
+            }
+
+            for entry in client_entries(plugin) {
+                self.broadcast(Message {
+                    type_: MessageType::ConfigPlugin,
+                    data: entry,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
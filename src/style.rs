@@ -1,12 +1,15 @@
 use std::{
     fs::{self, File},
     io::Write,
-    path::Path,
 };
 
+use crate::compress::{precompress_file, remove_siblings};
+use crate::fetch::{fetch_bytes, MAX_SCRIPT_SIZE};
+use crate::integrity::{check, digest};
+use crate::paths::data_path;
+use crate::scenes::lock_switch;
 use crate::{Message, MessageType, Server};
 
-use reqwest::blocking::get;
 use serde::{Deserialize, Serialize};
 use serde_json::{from_value, json, Value};
 use ws::Result;
@@ -17,35 +20,72 @@ pub struct Style {
     url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     inline: Option<String>,
+    /// The expected hash of the stylesheet in the format of subresource integrity.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    integrity: Option<String>,
+}
+
+/// Returns the hash of `data/style.css` recorded when it was saved.
+pub fn recorded_integrity() -> Option<String> {
+    let raw = fs::read_to_string(data_path("style.json")?).ok()?;
+    let value = serde_json::from_str::<Value>(&raw).ok()?;
+
+    value["integrity"].as_str().map(|s| s.to_string())
+}
+
+/// Returns the saved style, if any.
+pub fn saved_style() -> Option<Style> {
+    data_path("style.css")?.exists().then(|| Style {
+        url: Some("/custom/style.css".to_string()),
+        inline: None,
+        integrity: recorded_integrity(),
+    })
+}
+
+/// Precompresses a newly saved `data/style.css` and records its hash.
+///
+/// # Returns
+///
+/// * `Result<String, String>` - The hash of the style, or the reason it failed.
+pub fn record_style(style: &[u8]) -> std::result::Result<String, String> {
+    let path = data_path("style.css").ok_or("Invalid style path.")?;
+    precompress_file(&path);
+
+    let integrity = digest(style);
+    let meta_path = data_path("style.json").ok_or("Invalid style path.")?;
+    fs::write(meta_path, json!({ "integrity": integrity }).to_string())
+        .map_err(|_| "Failed to write style.")?;
+
+    Ok(integrity)
 }
 
 impl Server {
     pub fn remove_style(&self) -> Result<()> {
-        if Path::new("data/style.css").exists() {
-            if fs::remove_file("data/style.css").is_ok() {
-                return self.broadcast(Message {
-                    type_: MessageType::RemoveStyle,
-                    data: Value::Null,
-                });
+        let _guard = lock_switch();
+        let path = data_path("style.css").filter(|p| p.exists());
+        if let Some(path) = path.filter(|p| fs::remove_file(p).is_ok()) {
+            remove_siblings(&path);
+            if let Some(meta_path) = data_path("style.json") {
+                let _ = fs::remove_file(meta_path);
             }
+
+            return self.broadcast(Message {
+                type_: MessageType::RemoveStyle,
+                data: Value::Null,
+            });
         }
 
         self.send(Message::error("Failed to remove style."))
     }
 
     pub fn get_style(&self) -> Result<()> {
-        let file_path = "data/style.css";
-
         self.send(Message {
             type_: MessageType::GetStyle,
-            data: json!(Style {
+            data: json!(saved_style().unwrap_or(Style {
                 inline: None,
-                url: if Path::new(file_path).exists() {
-                    Some("/custom/style.css".to_string())
-                } else {
-                    None
-                }
-            }),
+                url: None,
+                integrity: None,
+            })),
         })
     }
 
@@ -59,36 +99,48 @@ impl Server {
             return self.send(Message::error("Failed to get style."));
         }
 
-        let style = match parsed.inline {
-            Some(s) => s,
-            None => {
-                let url = parsed.url.unwrap();
-                let resp = match get(url) {
-                    Ok(r) => r,
-                    Err(_) => return self.send(Message::error("Failed to get style.")),
-                };
-                match resp.text() {
-                    Ok(t) => t,
-                    Err(_) => return self.send(Message::error("Failed to get style.")),
-                }
-            }
+        let style = match (parsed.inline, &parsed.url) {
+            (Some(s), _) => s.into_bytes(),
+            (None, Some(url)) => match fetch_bytes(url, MAX_SCRIPT_SIZE) {
+                Ok(b) => b,
+                Err(e) => return self.send(Message::error(&e)),
+            },
+            (None, None) => return self.send(Message::error("Failed to get style.")),
         };
 
-        let mut file = match File::create("data/style.css") {
-            Ok(f) => f,
-            Err(_) => return self.send(Message::error("Failed to open style file.")),
+        // Checked against the bytes as downloaded, before any decoding.
+        if let Some(integrity) = &parsed.integrity {
+            let name = parsed.url.as_deref().unwrap_or("The inline style");
+            if let Err(e) = check(integrity, &style, name) {
+                return self.send(Message::error(&e));
+            }
+        }
+        if std::str::from_utf8(&style).is_err() {
+            return self.send(Message::error("The style is not valid UTF-8."));
+        }
+
+        let _guard = lock_switch();
+        let file = data_path("style.css").and_then(|p| File::create(p).ok());
+        let mut file = match file {
+            Some(f) => f,
+            None => return self.send(Message::error("Failed to open style file.")),
         };
 
-        if file.write_all(style.as_bytes()).is_err() {
+        if file.write_all(&style).is_err() {
             return self.send(Message::error("Failed to write style."));
         }
+        let integrity = match record_style(&style) {
+            Ok(i) => i,
+            Err(e) => return self.send(Message::error(&e)),
+        };
 
-        return self.broadcast(Message {
+        self.broadcast(Message {
             type_: MessageType::SetStyle,
             data: json!(Style {
                 url: Some("/custom/style.css".to_string()),
                 inline: None,
+                integrity: Some(integrity),
             }),
-        });
+        })
     }
 }
@@ -0,0 +1,173 @@
+use std::{fs, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, VerifyingKey};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::paths::data_path;
+use crate::serve::sha256_hex;
+
+/// What to do with plugins that are not signed by a trusted publisher.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TrustPolicy {
+    /// Don't check signatures at all.
+    #[default]
+    Off,
+    /// Check signatures, but install unverified plugins anyway.
+    Warn,
+    /// Refuse to install unverified plugins.
+    Require,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustedKey {
+    name: String,
+    /// The base64 encoded ed25519 public key.
+    public_key: String,
+}
+
+/// The contents of `data/trust.json`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TrustStore {
+    #[serde(default)]
+    policy: TrustPolicy,
+    #[serde(default)]
+    keys: Vec<TrustedKey>,
+}
+
+impl TrustStore {
+    /// Reads `data/trust.json`. Without it signatures aren't checked, but if it can't be
+    /// parsed, nothing may be installed until it is fixed, rather than silently
+    /// installing unsigned plugins.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<TrustStore, String>` - The store, or why installs are blocked.
+    pub fn load() -> Result<Self, String> {
+        let raw = match data_path("trust.json").and_then(|p| fs::read_to_string(p).ok()) {
+            Some(r) => r,
+            None => return Ok(TrustStore::default()),
+        };
+
+        serde_json::from_str(&raw).map_err(|e| {
+            error!("Failed to parse trust.json, installs are blocked: {}", e);
+            "trust.json is invalid, fix it to install plugins.".to_string()
+        })
+    }
+
+    pub fn policy(&self) -> TrustPolicy {
+        self.policy
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TrustStatus {
+    /// Signed by a trusted publisher.
+    Verified,
+    /// Not signed.
+    Unsigned,
+    /// Signed, but not by a trusted publisher, or the files were modified.
+    Invalid,
+    /// Installed with signature checks turned off.
+    Unchecked,
+}
+
+/// The outcome of the signature check, recorded in the plugin's meta.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Trust {
+    pub status: TrustStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+}
+
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<(String, Vec<u8>)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative = format!("{}{}", prefix, name);
+        let path = entry.path();
+
+        if path.is_dir() {
+            collect_files(&path, &format!("{}/", relative), files);
+        } else if let Ok(bytes) = fs::read(&path) {
+            files.push((relative, bytes));
+        }
+    }
+}
+
+/// Builds the text that a publisher signs, in the format of `sha256sum`: one line with
+/// the hash and path of every file of the plugin, sorted by path.
+///
+/// # Parameters
+///
+/// * `dir` - The directory the plugin was assembled in.
+/// * `meta` - The meta exactly as it was downloaded.
+pub fn manifest(dir: &Path, meta: &[u8]) -> String {
+    let mut files = Vec::new();
+    collect_files(dir, "", &mut files);
+
+    files.retain(|(path, _)| path != "meta.json");
+    files.push(("meta.json".to_string(), meta.to_vec()));
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    files
+        .iter()
+        .map(|(path, bytes)| format!("{}  {}\n", sha256_hex(bytes), path))
+        .collect()
+}
+
+/// Checks a detached signature of a manifest against the trusted keys.
+pub fn verify(store: &TrustStore, manifest: &str, signature: Option<&str>) -> Trust {
+    if store.policy == TrustPolicy::Off {
+        return Trust {
+            status: TrustStatus::Unchecked,
+            publisher: None,
+        };
+    }
+
+    let signature = match signature {
+        Some(s) => s,
+        None => {
+            return Trust {
+                status: TrustStatus::Unsigned,
+                publisher: None,
+            }
+        }
+    };
+
+    let signature = STANDARD
+        .decode(signature.trim())
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok());
+
+    let publisher = signature.and_then(|signature| {
+        store.keys.iter().find_map(|key| {
+            let bytes: [u8; 32] = STANDARD.decode(&key.public_key).ok()?.try_into().ok()?;
+            let verifying_key = VerifyingKey::from_bytes(&bytes).ok()?;
+
+            verifying_key
+                .verify_strict(manifest.as_bytes(), &signature)
+                .ok()
+                .map(|_| key.name.clone())
+        })
+    });
+
+    match publisher {
+        Some(name) => Trust {
+            status: TrustStatus::Verified,
+            publisher: Some(name),
+        },
+        None => Trust {
+            status: TrustStatus::Invalid,
+            publisher: None,
+        },
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    env, fs,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::instances::client_entries;
+use crate::paths::{backup_dir, plugin_dir, PluginName, StagingDir};
+use crate::plugin::{install_plugin, installed_plugins, read_installed, upstream_meta};
+use crate::scenes::lock_switch;
+use crate::{Message, MessageType, Server};
+
+use log::{info, warn};
+use semver::Version;
+use serde::Serialize;
+use serde_json::{json, Value};
+use ws::{Message as WSMessage, Result, Sender};
+
+/// How often plugins are checked for updates when `WIDGETBOX_UPDATE_INTERVAL` isn't set.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Whether a check requested by a client is running.
+static CHECKING: AtomicBool = AtomicBool::new(false);
+
+/// A plugin with a newer version available.
+#[derive(Clone, Serialize)]
+pub struct Update {
+    name: PluginName,
+    installed: String,
+    latest: String,
+}
+
+/// Parses a version, leniently accepting a leading `v` and a missing minor or patch
+/// version, as in `v1.2`.
+fn parse_version(version: &str) -> Option<Version> {
+    let version = version.trim().trim_start_matches('v');
+    let core_len = version.find(['-', '+']).unwrap_or(version.len());
+    let padding = 2usize.saturating_sub(version[..core_len].matches('.').count());
+
+    let padded = format!(
+        "{}{}{}",
+        &version[..core_len],
+        ".0".repeat(padding),
+        &version[core_len..]
+    );
+    Version::parse(&padded).ok()
+}
+
+/// Re-fetches the meta of every plugin installed from a URL and returns the ones with
+/// a newer version.
+pub fn available_updates() -> Vec<Update> {
+    let mut updates = Vec::new();
+
+    for plugin in installed_plugins() {
+        let source = match &plugin.source {
+            Some(s) => s,
+            None => continue,
+        };
+
+        let latest = match upstream_meta(source) {
+            Ok(meta) => meta["version"].as_str().unwrap_or_default().to_string(),
+            Err(e) => {
+                warn!("Failed to check {} for updates: {}", plugin.name, e);
+                continue;
+            }
+        };
+
+        match (parse_version(&plugin.version), parse_version(&latest)) {
+            (Some(installed), Some(upstream)) if upstream > installed => updates.push(Update {
+                name: plugin.name.clone(),
+                installed: plugin.version.clone(),
+                latest,
+            }),
+            (Some(_), Some(_)) => {}
+            _ => warn!(
+                "Failed to compare versions \"{}\" and \"{}\" of {}.",
+                plugin.version, latest, plugin.name
+            ),
+        }
+    }
+
+    updates
+}
+
+/// Periodically checks for updates and broadcasts them. The interval in seconds is read
+/// from `WIDGETBOX_UPDATE_INTERVAL`, where `0` disables the checks.
+pub fn spawn_checker(broadcaster: Sender) {
+    let interval = match env::var("WIDGETBOX_UPDATE_INTERVAL") {
+        Ok(secs) => match secs.trim().parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                warn!("Invalid WIDGETBOX_UPDATE_INTERVAL, using the default.");
+                DEFAULT_INTERVAL
+            }
+        },
+        Err(_) => DEFAULT_INTERVAL,
+    };
+
+    if interval.is_zero() {
+        return;
+    }
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        let updates = available_updates();
+        if updates.is_empty() {
+            continue;
+        }
+
+        info!("Updates are available for {} plugin(s).", updates.len());
+        let message = Message {
+            type_: MessageType::PluginUpdates,
+            data: json!(updates),
+        };
+        let _ = broadcaster.broadcast(WSMessage::Text(serde_json::to_string(&message).unwrap()));
+    });
+}
+
+/// Swaps an installed plugin with its backup.
+fn rollback(name: &PluginName) -> std::result::Result<(), String> {
+    let dir = plugin_dir(name).ok_or("Invalid plugin directory.")?;
+    let backup = backup_dir(name).ok_or("Invalid backup directory.")?;
+
+    let _guard = lock_switch();
+    if !backup.is_dir() {
+        return Err("No previous version to roll back to.".to_string());
+    }
+
+    // Keep the current version as the backup, so the rollback can be undone.
+    let staging = StagingDir::new().ok_or("Failed to create plugin directory.")?;
+    let current = staging.path().join("current");
+
+    if dir.exists() {
+        fs::rename(&dir, &current).map_err(|_| "Failed to move plugin.")?;
+    }
+
+    if fs::rename(&backup, &dir).is_err() {
+        let _ = fs::rename(&current, &dir);
+        return Err("Failed to restore previous version.".to_string());
+    }
+
+    if current.exists() {
+        fs::rename(&current, &backup).map_err(|_| "Failed to back up plugin.")?;
+    }
+
+    Ok(())
+}
+
+impl Server {
+    /// Checks every plugin installed from a URL for a newer version and broadcasts a
+    /// `pluginUpdates` message. Sources are fetched on a worker thread, so displays keep
+    /// being served meanwhile, and a check already running isn't started again.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn check_updates(&self) -> Result<()> {
+        if CHECKING.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let broadcaster = self.out.clone();
+        thread::spawn(move || {
+            let message = Message {
+                type_: MessageType::PluginUpdates,
+                data: json!(available_updates()),
+            };
+            CHECKING.store(false, Ordering::SeqCst);
+
+            let _ =
+                broadcaster.broadcast(WSMessage::Text(serde_json::to_string(&message).unwrap()));
+        });
+
+        Ok(())
+    }
+
+    /// Reinstalls a plugin from where it was installed from.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with a `name` property.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn upgrade_plugin(&self, data: Value) -> Result<()> {
+        let name = match data["name"].as_str().and_then(PluginName::parse) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get plugin.")),
+        };
+
+        let source = match read_installed(&name) {
+            Some(meta) => match meta.source {
+                Some(s) => s,
+                None => {
+                    return self.send(Message::error("The plugin wasn't installed from a URL."))
+                }
+            },
+            None => return self.send(Message::error("Plugin not found.")),
+        };
+
+        self.announce_install(install_plugin(&source.install_data()))
+    }
+
+    /// Restores the previously installed version of a plugin.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with a `name` property.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn rollback_plugin(&self, data: Value) -> Result<()> {
+        let name = match data["name"].as_str().and_then(PluginName::parse) {
+            Some(n) => n,
+            None => return self.send(Message::error("Failed to get plugin.")),
+        };
+
+        if let Err(e) = rollback(&name) {
+            return self.send(Message::error(&e));
+        }
+
+        let meta = match read_installed(&name) {
+            Some(m) => m,
+            None => return self.send(Message::error("Failed to read meta file.")),
+        };
+
+        for entry in client_entries(meta) {
+            self.broadcast(Message {
+                type_: MessageType::AddPlugin,
+                data: entry,
+            })?;
+        }
+
+        Ok(())
+    }
+}
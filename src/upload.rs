@@ -0,0 +1,363 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::instances::{
+    instance_id, instance_meta, instance_values, read_instances, write_instances,
+};
+use crate::paths::{plugin_dir, PluginName, StagingDir};
+use crate::plugin::{read_installed, write_installed, Config, ConfigValue};
+use crate::presets::preset_values;
+use crate::scenes::{lock_switch, scene_values};
+use crate::serve::content_type;
+use crate::{Message, MessageType, Server};
+
+use log::info;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use ws::Result;
+
+/// The directory inside the plugin directory uploads are saved to.
+pub const UPLOAD_DIR: &str = "uploads";
+/// The largest file accepted, unless the config allows less.
+const MAX_UPLOAD_SIZE: u64 = 32 * 1024 * 1024;
+/// The types of files that may be uploaded. Files that could run scripts when served
+/// from the same origin, such as HTML or SVG, are refused.
+const ALLOWED_TYPES: [&str; 24] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/bmp",
+    "image/x-icon",
+    "audio/mpeg",
+    "audio/ogg",
+    "audio/wav",
+    "audio/flac",
+    "video/mp4",
+    "video/webm",
+    "font/woff",
+    "font/woff2",
+    "font/ttf",
+    "font/otf",
+    "text/plain",
+    "text/csv",
+    "text/calendar",
+    "text/markdown",
+    "application/json",
+    "application/pdf",
+    "application/zip",
+];
+
+/// A file being uploaded by a connection, received as binary messages.
+pub struct PendingUpload {
+    plugin: PluginName,
+    /// The id of the instance the file is for, unless it is the default instance.
+    instance: Option<String>,
+    config: String,
+    extension: String,
+    size: u64,
+    received: u64,
+    hasher: Sha256,
+    file: File,
+    path: PathBuf,
+    // Removes the partial file if the upload is abandoned.
+    _staging: StagingDir,
+}
+
+/// Returns the type of a file from its name, without parameters such as the charset.
+fn mime_type(filename: &str) -> &'static str {
+    content_type(Path::new(filename))
+        .split(';')
+        .next()
+        .unwrap_or_default()
+}
+
+/// Checks whether a type matches one of the accepted types, such as `image/*`.
+fn is_accepted(mime: &str, accept: &[String]) -> bool {
+    accept
+        .iter()
+        .any(|pattern| match pattern.strip_suffix("/*") {
+            Some(prefix) => mime.split('/').next() == Some(prefix),
+            None => pattern == mime,
+        })
+}
+
+/// Returns the name of the uploaded file a config value points to.
+fn uploaded_file(value: &Value) -> Option<&str> {
+    let url = value.as_str()?.split(['?', '#']).next()?;
+    let (_, filename) = url.rsplit_once(&format!("/{}/", UPLOAD_DIR))?;
+
+    Some(filename)
+}
+
+/// Removes the uploads no config, instance, preset or scene of the plugin points to
+/// anymore.
+///
+/// # Parameters
+///
+/// * `dir` - The plugin directory.
+/// * `plugin` - The name of the plugin.
+/// * `configs` - The configs of the plugin.
+pub fn prune_uploads(dir: &Path, plugin: &PluginName, configs: &[Config]) {
+    let entries = match fs::read_dir(dir.join(UPLOAD_DIR)) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    let uploads: Vec<&Config> = configs.iter().filter(|c| c.type_ == "upload").collect();
+    let saved_values: Vec<ConfigValue> = preset_values(plugin)
+        .into_iter()
+        .chain(scene_values(plugin))
+        .chain(instance_values(plugin))
+        .collect();
+
+    let referenced: Vec<&str> = uploads
+        .iter()
+        .filter_map(|c| c.value.as_ref())
+        .chain(
+            saved_values
+                .iter()
+                .filter(|cv| uploads.iter().any(|c| c.name == cv.name))
+                .map(|cv| &cv.value),
+        )
+        .filter_map(uploaded_file)
+        .collect();
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Precompressed copies are kept along with their file.
+        let is_referenced = referenced
+            .iter()
+            .any(|r| name == *r || name.starts_with(&format!("{}.", r)));
+
+        if !is_referenced {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Copies the uploads of an installed plugin into the directory of its new version.
+pub fn copy_uploads(from: &Path, to: &Path) -> std::result::Result<(), String> {
+    let entries = match fs::read_dir(from.join(UPLOAD_DIR)) {
+        Ok(e) => e,
+        Err(_) => return Ok(()),
+    };
+
+    fs::create_dir_all(to.join(UPLOAD_DIR)).map_err(|_| "Failed to copy uploads.")?;
+    for entry in entries.flatten() {
+        fs::copy(entry.path(), to.join(UPLOAD_DIR).join(entry.file_name()))
+            .map_err(|_| "Failed to copy uploads.")?;
+    }
+
+    Ok(())
+}
+
+impl Server {
+    /// Starts uploading a file for an `upload` config. The file is then sent as binary
+    /// messages, and once all of it has been received, the config is set to its URL.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - A JSON object with the `name` of the plugin, the name of the `config`,
+    ///   the `filename` and `size` of the file, and optionally the `id` of the instance.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Indicates success or failure of the operation.
+    pub fn upload_file(&mut self, data: Value) -> Result<()> {
+        self.upload = None;
+
+        let (plugin, config_name, filename, size) = match (
+            data["name"].as_str().and_then(PluginName::parse),
+            data["config"].as_str(),
+            data["filename"].as_str(),
+            data["size"].as_u64(),
+        ) {
+            (Some(p), Some(c), Some(f), Some(s)) => (p, c, f, s),
+            _ => return self.send(Message::error("Failed to parse data.")),
+        };
+
+        let meta = match read_installed(&plugin) {
+            Some(m) => m,
+            None => return self.send(Message::error("Plugin not found.")),
+        };
+        let instance = instance_id(&plugin, data["id"].as_str());
+        if instance.is_some_and(|id| !read_instances(&plugin).iter().any(|i| i.id == id)) {
+            return self.send(Message::error("Instance not found."));
+        }
+        let config = match meta
+            .configs
+            .iter()
+            .flatten()
+            .find(|c| c.name == config_name && c.type_ == "upload")
+        {
+            Some(c) => c,
+            None => return self.send(Message::error("Upload config not found.")),
+        };
+
+        let max_size = config
+            .max_size
+            .unwrap_or(MAX_UPLOAD_SIZE)
+            .min(MAX_UPLOAD_SIZE);
+        if size == 0 || size > max_size {
+            return self.send(Message::error(&format!(
+                "The file must be between 1 and {} bytes.",
+                max_size
+            )));
+        }
+
+        let mime = mime_type(filename);
+        let accepted = config.accept.as_ref().is_none_or(|a| is_accepted(mime, a));
+        if !ALLOWED_TYPES.contains(&mime) || !accepted {
+            return self.send(Message::error("The type of the file is not allowed."));
+        }
+
+        let staging = match StagingDir::new() {
+            Some(s) => s,
+            None => return self.send(Message::error("Failed to create upload file.")),
+        };
+        let path = staging.path().join("upload");
+        let file = match File::create(&path) {
+            Ok(f) => f,
+            Err(_) => return self.send(Message::error("Failed to create upload file.")),
+        };
+
+        self.upload = Some(PendingUpload {
+            plugin: plugin.clone(),
+            instance: instance.map(str::to_string),
+            config: config_name.to_string(),
+            extension: Path::new(filename)
+                .extension()
+                .map(|e| e.to_string_lossy().to_ascii_lowercase())
+                .unwrap_or_default(),
+            size,
+            received: 0,
+            hasher: Sha256::new(),
+            file,
+            path,
+            _staging: staging,
+        });
+
+        self.send(Message {
+            type_: MessageType::UploadFile,
+            data: json!({
+                "name": plugin,
+                "config": config_name,
+                "size": size,
+            }),
+        })
+    }
+
+    /// Appends a binary message to the pending upload.
+    pub fn receive_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        let upload = match self.upload.as_mut() {
+            Some(u) => u,
+            None => return self.send(Message::error("Binary format is not supported.")),
+        };
+
+        upload.received += chunk.len() as u64;
+        if upload.received > upload.size {
+            self.upload = None;
+            return self.send(Message::error("The file is larger than announced."));
+        }
+
+        upload.hasher.update(chunk);
+        if upload.file.write_all(chunk).is_err() {
+            self.upload = None;
+            return self.send(Message::error("Failed to write upload file."));
+        }
+
+        if upload.received < upload.size {
+            return Ok(());
+        }
+
+        let upload = self.upload.take().unwrap();
+        match finish_upload(upload) {
+            Ok(meta) => self.broadcast(Message {
+                type_: MessageType::ConfigPlugin,
+                data: meta,
+            }),
+            Err(e) => self.send(Message::error(&e)),
+        }
+    }
+}
+
+/// Moves a completely received file into the plugin directory and points its config
+/// to it.
+///
+/// # Returns
+///
+/// * `Result<Value, String>` - The updated meta of the plugin or instance, or the reason
+///   it failed.
+fn finish_upload(upload: PendingUpload) -> std::result::Result<Value, String> {
+    upload
+        .file
+        .sync_all()
+        .map_err(|_| "Failed to write upload file.")?;
+
+    let hash: String = upload
+        .hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let filename = match upload.extension.as_str() {
+        "" => hash[..16].to_string(),
+        extension => format!("{}.{}", &hash[..16], extension),
+    };
+
+    let _guard = lock_switch();
+    // The plugin may have been removed during the upload.
+    let mut meta = read_installed(&upload.plugin).ok_or("Plugin not found.")?;
+
+    let dir = plugin_dir(&upload.plugin).ok_or("Invalid plugin directory.")?;
+    let target = dir.join(UPLOAD_DIR).join(&filename);
+    fs::create_dir_all(dir.join(UPLOAD_DIR))
+        .and_then(|_| fs::rename(&upload.path, &target))
+        .map_err(|_| "Failed to save upload file.")?;
+
+    let url = format!("/plugin/{}/{}/{}", upload.plugin, UPLOAD_DIR, filename);
+    let value = ConfigValue {
+        name: upload.config.clone(),
+        value: Value::String(url),
+    };
+
+    let updated = match &upload.instance {
+        Some(id) => {
+            let mut instances = read_instances(&upload.plugin);
+            let instance = instances
+                .iter_mut()
+                .find(|i| &i.id == id)
+                .ok_or("Instance not found.")?;
+            instance.configs.retain(|cv| cv.name != value.name);
+            instance.configs.push(value);
+            let instance = instance.clone();
+
+            write_instances(&upload.plugin, &instances)?;
+            instance_meta(&meta, &instance)
+        }
+        None => {
+            for config in meta.configs.iter_mut().flatten() {
+                if config.name == value.name {
+                    config.value = Some(value.value.clone());
+                }
+            }
+
+            write_installed(&meta)?;
+            meta.clone()
+        }
+    };
+
+    prune_uploads(
+        &dir,
+        &upload.plugin,
+        meta.configs.as_deref().unwrap_or_default(),
+    );
+    info!("Uploaded {} for plugin {}.", filename, upload.plugin);
+
+    Ok(updated.into_client())
+}
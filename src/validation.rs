@@ -0,0 +1,548 @@
+use std::path::Path;
+
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::paths::{confine, plugin_dir, PluginName};
+use crate::plugin::{read_installed, Config};
+use crate::schema;
+use crate::settings::SETTING_NAMES;
+use crate::upload::UPLOAD_DIR;
+
+/// The largest value of a `webComponent` or `object` config, serialized.
+const MAX_JSON_VALUE_SIZE: usize = 64 * 1024;
+/// Tolerance of the `step` check, so decimal steps such as `0.1` aren't rejected because
+/// of floating point rounding.
+const STEP_EPSILON: f64 = 1e-9;
+
+/// Why the value of a config was rejected.
+#[derive(Clone, Serialize)]
+pub struct FieldError {
+    pub name: String,
+    pub message: String,
+}
+
+fn check_number(config: &Config, number: f64) -> Result<(), String> {
+    if let Some(min) = config.min {
+        if number < min {
+            return Err(format!("Must be at least {}.", min));
+        }
+    }
+
+    if let Some(max) = config.max {
+        if number > max {
+            return Err(format!("Must be at most {}.", max));
+        }
+    }
+
+    if let Some(step) = config.step.filter(|s| *s > 0.0) {
+        let steps = (number - config.min.unwrap_or(0.0)) / step;
+        if (steps - steps.round()).abs() > STEP_EPSILON {
+            return Err(format!("Must be a multiple of {}.", step));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_string(config: &Config, string: &str) -> Result<(), String> {
+    if let Some(max_length) = config.max_length {
+        if string.chars().count() > max_length {
+            return Err(format!("Must be at most {} characters long.", max_length));
+        }
+    }
+
+    if let Some(pattern) = &config.pattern {
+        // Like the `pattern` attribute of inputs, the whole value must match.
+        let re = Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|_| format!("Invalid pattern {}.", pattern))?;
+
+        if !re.is_match(string) {
+            return Err(format!("Must match the pattern {}.", pattern));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_size(value: &Value) -> Result<(), String> {
+    match value.to_string().len() <= MAX_JSON_VALUE_SIZE {
+        true => Ok(()),
+        false => Err(format!(
+            "Must be at most {} bytes as JSON.",
+            MAX_JSON_VALUE_SIZE
+        )),
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(s) => s.is_empty(),
+        Value::Array(a) => a.is_empty(),
+        _ => false,
+    }
+}
+
+/// Parses a number, also from a string such as `"3.5"`.
+fn parse_number(value: &Value) -> Option<f64> {
+    let number = match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+
+    number.filter(|n| n.is_finite())
+}
+
+/// Returns a number as an integer when it is whole, so `3.0` is stored as `3`.
+fn number_value(number: f64) -> Value {
+    match number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+        true => json!(number as i64),
+        false => json!(number),
+    }
+}
+
+/// Parses a color as `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, `rgb(r, g, b)` or
+/// `rgba(r, g, b, a)`.
+///
+/// # Returns
+///
+/// * `Option<String>` - The color as lowercase `#rrggbb`, or `#rrggbbaa` if it isn't
+///   opaque.
+fn parse_color(color: &str) -> Option<String> {
+    let color = color.trim().to_ascii_lowercase();
+
+    let rgba: [u8; 4] = match color.strip_prefix('#') {
+        Some(hex) if hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            let channels: Vec<u8> = match hex.len() {
+                3 | 4 => hex
+                    .chars()
+                    .map(|c| c.to_digit(16).unwrap() as u8 * 17)
+                    .collect(),
+                6 | 8 => (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                    .collect(),
+                _ => return None,
+            };
+
+            [
+                channels[0],
+                channels[1],
+                channels[2],
+                channels.get(3).copied().unwrap_or(255),
+            ]
+        }
+        Some(_) => return None,
+        None => {
+            let re = Regex::new(
+                r"^rgba?\(\s*(\d{1,3})\s*,\s*(\d{1,3})\s*,\s*(\d{1,3})\s*(?:,\s*([\d.]+)\s*)?\)$",
+            )
+            .unwrap();
+            let caps = re.captures(&color)?;
+
+            let channel = |i: usize| caps[i].parse::<u8>().ok();
+            let alpha = match caps.get(4) {
+                Some(a) => a
+                    .as_str()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|a| (0.0..=1.0).contains(a))?,
+                None => 1.0,
+            };
+
+            [
+                channel(1)?,
+                channel(2)?,
+                channel(3)?,
+                (alpha * 255.0).round() as u8,
+            ]
+        }
+    };
+
+    let hex: String = rgba.iter().map(|c| format!("{:02x}", c)).collect();
+    match rgba[3] {
+        255 => Some(format!("#{}", &hex[..6])),
+        _ => Some(format!("#{}", hex)),
+    }
+}
+
+/// Parses a 24-hour time as `H:MM` or `H:MM:SS` and returns it zero-padded.
+pub fn parse_time(time: &str) -> Option<String> {
+    let re = Regex::new(r"^(\d{1,2}):(\d{2})(?::(\d{2}))?$").unwrap();
+    let caps = re.captures(time.trim())?;
+
+    let hours: u32 = caps[1].parse().ok()?;
+    let minutes: u32 = caps[2].parse().ok()?;
+    let seconds: Option<u32> = caps.get(3).and_then(|s| s.as_str().parse().ok());
+
+    if hours > 23 || minutes > 59 || seconds.is_some_and(|s| s > 59) {
+        return None;
+    }
+
+    match seconds {
+        Some(s) => Some(format!("{:02}:{:02}:{:02}", hours, minutes, s)),
+        None => Some(format!("{:02}:{:02}", hours, minutes)),
+    }
+}
+
+/// Whether a URL points to a file uploaded for the plugin, as set by `finish_upload`.
+fn is_uploaded(plugin: &PluginName, url: &str) -> bool {
+    let prefix = format!("/plugin/{}/{}/", plugin, UPLOAD_DIR);
+    let filename = match url.strip_prefix(&prefix) {
+        Some(f) if !f.is_empty() && !f.contains('/') => f,
+        _ => return false,
+    };
+
+    plugin_dir(plugin)
+        .and_then(|dir| confine(&dir.join(UPLOAD_DIR), Path::new(filename)))
+        .is_some_and(|path| path.is_file())
+}
+
+/// Returns the items of a list value, accepting a single value as a list of one.
+fn list_items(value: &Value) -> Vec<Value> {
+    match value {
+        Value::Array(items) => items.clone(),
+        value => vec![value.clone()],
+    }
+}
+
+/// Checks a value against the type and constraints of a config and returns it in its
+/// normalized form, such as a color as `#rrggbb` or a time as `HH:MM`. Types the server
+/// doesn't know about are left to the plugin, but the constraints still apply.
+///
+/// # Parameters
+///
+/// * `plugin` - The plugin the config belongs to.
+/// * `config` - The config to check the value against.
+/// * `value` - The value to check.
+///
+/// # Returns
+///
+/// * `Result<Value, String>` - The normalized value, or why it is invalid.
+pub fn normalize(plugin: &PluginName, config: &Config, value: &Value) -> Result<Value, String> {
+    if is_empty(value) {
+        return match config.required {
+            true => Err("Is required.".to_string()),
+            false => Ok(value.clone()),
+        };
+    }
+
+    match config.type_.as_str() {
+        "checkbox" if value.is_boolean() => {}
+        "checkbox" => return Err("Must be true or false.".to_string()),
+        "number" | "range" => {
+            let number = parse_number(value).ok_or("Must be a number.")?;
+            check_number(config, number)?;
+
+            return Ok(number_value(number));
+        }
+        "text" if value.is_string() => {}
+        "text" => return Err("Must be a string.".to_string()),
+        "secret" if value.is_string() => {}
+        "secret" => return Err("Must be a string.".to_string()),
+        "upload" if value.as_str().is_some_and(|s| is_uploaded(plugin, s)) => {}
+        "upload" => return Err("Must be an uploaded file.".to_string()),
+        "select" => {
+            let valid = config.options.iter().flatten().any(|o| &o.value() == value);
+            if !valid {
+                return Err("Must be one of the options.".to_string());
+            }
+        }
+        "color" => {
+            return value
+                .as_str()
+                .and_then(parse_color)
+                .map(Value::String)
+                .ok_or("Must be a color such as #rrggbb or rgb(r, g, b).".to_string());
+        }
+        "time" => {
+            return value
+                .as_str()
+                .and_then(parse_time)
+                .map(Value::String)
+                .ok_or("Must be a time such as 08:30.".to_string());
+        }
+        "date" => {
+            return value
+                .as_str()
+                .and_then(|d| NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok())
+                .map(|d| Value::String(d.format("%Y-%m-%d").to_string()))
+                .ok_or("Must be a date such as 2025-01-31.".to_string());
+        }
+        "multiselect" => {
+            let selected = list_items(value);
+            let options: Vec<Value> = config.options.iter().flatten().map(|o| o.value()).collect();
+
+            if let Some(invalid) = selected.iter().find(|v| !options.contains(v)) {
+                return Err(format!("{} is not one of the options.", invalid));
+            }
+
+            // Keep the order of the options and drop duplicates.
+            return Ok(Value::Array(
+                options
+                    .into_iter()
+                    .filter(|o| selected.contains(o))
+                    .collect(),
+            ));
+        }
+        "list" => {
+            let mut items = Vec::new();
+            for item in list_items(value) {
+                let item = item.as_str().ok_or("Must be a list of strings.")?.trim();
+                if item.is_empty() {
+                    continue;
+                }
+
+                check_string(config, item).map_err(|e| format!("\"{}\": {}", item, e))?;
+                items.push(Value::String(item.to_string()));
+            }
+
+            if let Some(max) = config.max.filter(|max| items.len() as f64 > *max) {
+                return Err(format!("Must have at most {} items.", max));
+            }
+
+            return Ok(Value::Array(items));
+        }
+        "object" => {
+            if !value.is_object() {
+                return Err("Must be an object.".to_string());
+            }
+            check_size(value)?;
+
+            return match &config.schema {
+                Some(s) => schema::check(s, value),
+                None => Ok(value.clone()),
+            };
+        }
+        "pluginRef" => {
+            return match value
+                .as_str()
+                .and_then(PluginName::parse)
+                .filter(|n| read_installed(n).is_some())
+            {
+                Some(name) => Ok(Value::String(name.to_string())),
+                None => Err("Must be an installed plugin.".to_string()),
+            };
+        }
+        "webComponent" => {
+            // The value belongs to the plugin's own editor, only its size is checked.
+            check_size(value)?;
+
+            return Ok(value.clone());
+        }
+        _ => {}
+    }
+
+    match value {
+        Value::Number(n) => check_number(config, n.as_f64().unwrap_or_default()),
+        Value::String(s) => check_string(config, s),
+        _ => Ok(()),
+    }?;
+
+    Ok(value.clone())
+}
+
+/// Checks the default of every config and replaces it with its normalized form. A
+/// required config may be left empty by default, for the user to fill in.
+pub fn normalize_defaults(plugin: &PluginName, configs: &mut [Config]) -> Result<(), String> {
+    for config in configs.iter_mut() {
+        if is_empty(&config.default) {
+            continue;
+        }
+
+        let result = match config.type_.as_str() {
+            // The plugin a default refers to doesn't have to be installed yet.
+            "pluginRef" => match config.default.as_str().and_then(PluginName::parse) {
+                Some(_) => Ok(config.default.clone()),
+                None => Err("Must be a plugin name.".to_string()),
+            },
+            // Files are only ever uploaded by the user.
+            "upload" => Err("Must be empty.".to_string()),
+            _ => normalize(plugin, config, &config.default),
+        };
+
+        match result {
+            Ok(default) => config.default = default,
+            Err(e) => {
+                return Err(format!(
+                    "Invalid default of config \"{}\": {}",
+                    config.name, e
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a config can hold the value of a setting, since inherited values are sent
+/// to clients as they are.
+fn can_inherit(config: &Config, setting: &str) -> bool {
+    let has_options = |values: [&str; 2]| {
+        values
+            .iter()
+            .all(|v| config.options.iter().flatten().any(|o| o.value() == *v))
+    };
+
+    match (config.type_.as_str(), setting) {
+        ("text", "locale" | "timezone" | "clock" | "temperatureUnit") => true,
+        ("select", "clock") => has_options(["12h", "24h"]),
+        ("select", "temperatureUnit") => has_options(["celsius", "fahrenheit"]),
+        ("object", "location") => config.schema.as_ref().is_none_or(|s| {
+            schema::check(s, &json!({ "latitude": 0.0, "longitude": 0.0 })).is_ok()
+        }),
+        _ => false,
+    }
+}
+
+/// Checks that every config declares what its type needs, such as the bounds of a
+/// `range` or the custom element of a `webComponent`.
+///
+/// # Parameters
+///
+/// * `configs` - The configs of the plugin.
+/// * `has_editor` - Whether the plugin has an editor script to define custom elements.
+pub fn validate_declarations(configs: &[Config], has_editor: bool) -> Result<(), String> {
+    for config in configs {
+        let error = match config.type_.as_str() {
+            "webComponent" if !has_editor => "needs an editor script to define its element",
+            // Custom element names follow the same rules as plugin names.
+            "webComponent"
+                if config
+                    .element
+                    .as_deref()
+                    .is_none_or(|e| PluginName::parse(e).is_none()) =>
+            {
+                "must name a valid custom element"
+            }
+            "range" if config.min.is_none() || config.max.is_none() => {
+                "must declare a min and a max"
+            }
+            "multiselect" if config.options.as_ref().is_none_or(|o| o.is_empty()) => {
+                "must declare its options"
+            }
+            // Defaults are part of the meta, which isn't secret.
+            "secret" if !is_empty(&config.default) => "must not declare a default",
+            "secret"
+                if config.hosts.iter().flatten().any(|h| {
+                    h.is_empty()
+                        || !h
+                            .chars()
+                            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '-' | '.'))
+                }) =>
+            {
+                "must declare its hosts as lowercase host names"
+            }
+            _ if config.type_ != "secret" && config.hosts.is_some() => {
+                "may only declare hosts if it is a secret"
+            }
+            "object" if config.schema.as_ref().is_some_and(|s| !s.is_object()) => {
+                "must declare its schema as an object"
+            }
+            _ if config
+                .inherit
+                .as_deref()
+                .is_some_and(|s| !SETTING_NAMES.contains(&s)) =>
+            {
+                "must inherit a known setting"
+            }
+            _ if config
+                .inherit
+                .as_deref()
+                .is_some_and(|s| !can_inherit(config, s)) =>
+            {
+                "can't hold the value of the setting it inherits"
+            }
+            _ => continue,
+        };
+
+        return Err(format!("Config \"{}\" {}.", config.name, error));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_colors() {
+        assert_eq!(parse_color("#FFF"), Some("#ffffff".to_string()));
+        assert_eq!(parse_color(" #12ab34 "), Some("#12ab34".to_string()));
+        assert_eq!(parse_color("#12ab34ff"), Some("#12ab34".to_string()));
+        assert_eq!(parse_color("#0008"), Some("#00000088".to_string()));
+        assert_eq!(parse_color("rgb(255, 0, 10)"), Some("#ff000a".to_string()));
+        assert_eq!(
+            parse_color("RGBA(0,0,0,0.5)"),
+            Some("#00000080".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_colors() {
+        for color in [
+            "",
+            "#",
+            "#12",
+            "#12345",
+            "#ggg",
+            "red",
+            "rgb(256, 0, 0)",
+            "rgb(0, 0)",
+            "rgba(0, 0, 0, 2)",
+        ] {
+            assert_eq!(parse_color(color), None, "{}", color);
+        }
+    }
+
+    #[test]
+    fn normalizes_times() {
+        assert_eq!(parse_time("8:30"), Some("08:30".to_string()));
+        assert_eq!(parse_time(" 23:59 "), Some("23:59".to_string()));
+        assert_eq!(parse_time("00:00:05"), Some("00:00:05".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        for time in [
+            "", "24:00", "12:60", "12:00:60", "1230", "12:5", "-1:00", "123:00",
+        ] {
+            assert_eq!(parse_time(time), None, "{}", time);
+        }
+    }
+
+    #[test]
+    fn limits_inherited_settings() {
+        let config = |value: Value| serde_json::from_value::<Config>(value).unwrap();
+
+        let text = config(json!({ "name": "a", "type": "text", "default": "" }));
+        assert!(can_inherit(&text, "timezone"));
+        assert!(!can_inherit(&text, "location"));
+
+        let checkbox = config(json!({ "name": "a", "type": "checkbox", "default": false }));
+        assert!(!can_inherit(&checkbox, "location"));
+        assert!(!can_inherit(&checkbox, "clock"));
+
+        let select = config(json!({
+            "name": "a",
+            "type": "select",
+            "default": "12h",
+            "options": [{ "name": "12h" }, { "name": "24h" }],
+        }));
+        assert!(can_inherit(&select, "clock"));
+        assert!(!can_inherit(&select, "temperatureUnit"));
+
+        let object = config(json!({
+            "name": "a",
+            "type": "object",
+            "default": null,
+            "schema": { "type": "array" },
+        }));
+        assert!(!can_inherit(&object, "location"));
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+};
+
+use regex::Regex;
+use url::Url;
+
+use crate::fetch::fetch_bytes;
+use crate::paths::confine;
+use crate::serve::sha256_hex;
+
+/// The directory inside the plugin directory that remote modules are saved to.
+const VENDOR_DIR: &str = "vendor";
+/// The largest number of modules a plugin may pull in.
+const MAX_MODULES: usize = 256;
+/// The largest size of a single module.
+const MAX_MODULE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Finds the specifiers of static imports and exports, and of dynamic imports of a
+/// string literal.
+///
+/// # Returns
+///
+/// * `Vec<(Range<usize>, String)>` - The position of each specifier, without quotes,
+///   and the specifier itself.
+fn specifiers(source: &str) -> Vec<(Range<usize>, String)> {
+    let static_re = Regex::new(
+        r#"(?:^|[;\s}])(?:import|export)\s*(?:[\w$*{}\s,]+?\s*from\s*)?["']([^"'\s]+)["']"#,
+    )
+    .unwrap();
+    let dynamic_re = Regex::new(r#"\bimport\s*\(\s*["']([^"'\s]+)["']\s*\)"#).unwrap();
+
+    let mut found: Vec<(Range<usize>, String)> = static_re
+        .captures_iter(source)
+        .chain(dynamic_re.captures_iter(source))
+        .filter_map(|caps| caps.get(1))
+        .map(|m| (m.range(), m.as_str().to_string()))
+        .collect();
+
+    found.sort_by_key(|(range, _)| range.start);
+    found.dedup_by_key(|(range, _)| range.start);
+    found
+}
+
+/// Where an import points to.
+enum Target {
+    Remote(Url),
+    Local(PathBuf),
+}
+
+/// Resolves a specifier found in a module. Bare specifiers, such as `lit`, are left to
+/// the browser.
+///
+/// # Parameters
+///
+/// * `specifier` - The specifier to resolve.
+/// * `module` - The path of the importing module, relative to the plugin directory.
+/// * `base` - The URL the importing module was downloaded from, if any.
+/// * `source_dir` - The directory of a side-loaded script. Modules read from the local
+///   disk may only import files inside it.
+fn resolve(
+    specifier: &str,
+    module: &Path,
+    base: Option<&Url>,
+    source_dir: Option<&Path>,
+) -> Option<Target> {
+    if specifier.starts_with("http://") || specifier.starts_with("https://") {
+        return Url::parse(specifier).ok().map(Target::Remote);
+    }
+
+    let is_relative =
+        specifier.starts_with('/') || specifier.starts_with("./") || specifier.starts_with("../");
+    if !is_relative {
+        return None;
+    }
+
+    if let Some(base) = base {
+        let url = base.join(specifier).ok()?;
+
+        return match url.scheme() {
+            "http" | "https" => Some(Target::Remote(url)),
+            "file" => {
+                let source_dir = source_dir?;
+                let path = url.to_file_path().ok()?;
+                let relative = path.strip_prefix(source_dir).ok()?;
+
+                confine(source_dir, relative).map(|_| Target::Remote(url))
+            }
+            _ => None,
+        };
+    }
+
+    if specifier.starts_with('/') {
+        return None;
+    }
+
+    // Resolve a relative import of a file shipped with the plugin.
+    let mut path = module.parent().unwrap_or(Path::new("")).to_path_buf();
+    for component in Path::new(specifier).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::ParentDir => {
+                if !path.pop() {
+                    return None;
+                }
+            }
+            _ => continue,
+        }
+    }
+
+    Some(Target::Local(path))
+}
+
+/// Returns the specifier pointing from `module` to `target`, both relative to the
+/// plugin directory.
+fn relative_specifier(module: &Path, target: &Path) -> String {
+    let depth = module.parent().map_or(0, |p| p.components().count());
+
+    match depth {
+        0 => format!("./{}", target.display()),
+        _ => format!("{}{}", "../".repeat(depth), target.display()),
+    }
+}
+
+/// Downloads the remote ES modules imported by a script into the plugin directory and
+/// rewrites the specifiers to point to the local copies, so the plugin keeps working
+/// offline. Imports are followed recursively, through remote modules as well as files
+/// shipped with the plugin.
+///
+/// # Parameters
+///
+/// * `dir` - The plugin directory.
+/// * `entry` - The script to start from, relative to `dir`.
+/// * `base` - The URL the script was downloaded from, if any. Relative imports of a
+///   downloaded script are resolved against it. Imports of a side-loaded script must
+///   stay inside its directory.
+pub fn vendor_modules(dir: &Path, entry: &str, base: Option<Url>) -> Result<(), String> {
+    let source_dir = base
+        .as_ref()
+        .filter(|b| b.scheme() == "file")
+        .and_then(|b| b.to_file_path().ok())
+        .and_then(|p| p.parent().map(Path::to_path_buf));
+
+    let mut vendored: HashMap<Url, PathBuf> = HashMap::new();
+    let mut visited = vec![PathBuf::from(entry)];
+    let mut queue = VecDeque::from([(PathBuf::from(entry), base)]);
+
+    while let Some((module, base)) = queue.pop_front() {
+        let file_path = match confine(dir, &module) {
+            Some(p) if p.is_file() => p,
+            _ => continue,
+        };
+
+        let source = match fs::read_to_string(&file_path) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+
+        let mut rewritten = source.clone();
+        for (range, specifier) in specifiers(&source).into_iter().rev() {
+            match resolve(&specifier, &module, base.as_ref(), source_dir.as_deref()) {
+                Some(Target::Remote(mut url)) => {
+                    url.set_fragment(None);
+
+                    let target = match vendored.get(&url) {
+                        Some(t) => t.clone(),
+                        None => {
+                            if vendored.len() >= MAX_MODULES {
+                                return Err(format!(
+                                    "Plugin imports more than {} modules.",
+                                    MAX_MODULES
+                                ));
+                            }
+
+                            let bytes = fetch_bytes(url.as_str(), MAX_MODULE_SIZE)?;
+                            let target = Path::new(VENDOR_DIR)
+                                .join(format!("{}.js", &sha256_hex(url.as_str().as_bytes())[..16]));
+
+                            let target_path =
+                                confine(dir, &target).ok_or("Invalid plugin directory.")?;
+                            fs::create_dir_all(target_path.parent().unwrap())
+                                .and_then(|_| fs::write(&target_path, bytes))
+                                .map_err(|_| format!("Failed to save {}.", url))?;
+
+                            vendored.insert(url.clone(), target.clone());
+                            queue.push_back((target.clone(), Some(url)));
+                            target
+                        }
+                    };
+
+                    rewritten.replace_range(range, &relative_specifier(&module, &target));
+                }
+                Some(Target::Local(path)) => {
+                    if !visited.contains(&path) {
+                        visited.push(path.clone());
+                        queue.push_back((path, None));
+                    }
+                }
+                None => continue,
+            }
+        }
+
+        if rewritten != source {
+            fs::write(&file_path, rewritten)
+                .map_err(|_| format!("Failed to rewrite {}.", module.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the specifiers found in `source`, checking that each range points to it.
+    fn found(source: &str) -> Vec<String> {
+        specifiers(source)
+            .into_iter()
+            .map(|(range, specifier)| {
+                assert_eq!(&source[range], specifier);
+                specifier
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_static_imports() {
+        let source = r#"
+import { html, css } from "https://esm.sh/lit";
+import * as d3 from './d3.js';
+import Default, { a as b } from "../lib/a.js";
+import "./side-effect.js";
+import'./no-space.js';
+"#;
+
+        assert_eq!(
+            found(source),
+            [
+                "https://esm.sh/lit",
+                "./d3.js",
+                "../lib/a.js",
+                "./side-effect.js",
+                "./no-space.js",
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_exports_from() {
+        let source = r#"export { a, b } from "./a.js";
+export * from './b.js';
+export * as c from "./c.js";
+export const d = 1;"#;
+
+        assert_eq!(found(source), ["./a.js", "./b.js", "./c.js"]);
+    }
+
+    #[test]
+    fn finds_dynamic_imports() {
+        let source = r#"const m = await import("./lazy.js");
+const n = import( 'https://cdn.example/n.js' );
+const o = import(path);"#;
+
+        assert_eq!(found(source), ["./lazy.js", "https://cdn.example/n.js"]);
+    }
+
+    #[test]
+    fn ignores_strings_containing_import() {
+        let source = r#"const help = "import the data first";
+console.log('important');
+const reimport = 'x';"#;
+
+        assert!(found(source).is_empty());
+    }
+
+    #[test]
+    fn finds_imports_after_other_statements() {
+        let source = r#"const a = 1;import "./a.js";
+if (a) {}import("./b.js");"#;
+
+        assert_eq!(found(source), ["./a.js", "./b.js"]);
+    }
+
+    #[test]
+    fn points_to_targets_from_the_root() {
+        assert_eq!(
+            relative_specifier(Path::new("index.js"), Path::new("vendor/a.js")),
+            "./vendor/a.js"
+        );
+    }
+
+    #[test]
+    fn points_to_targets_from_nested_modules() {
+        assert_eq!(
+            relative_specifier(Path::new("vendor/a.js"), Path::new("vendor/b.js")),
+            "../vendor/b.js"
+        );
+        assert_eq!(
+            relative_specifier(Path::new("src/lib/c.js"), Path::new("vendor/d.js")),
+            "../../vendor/d.js"
+        );
+    }
+
+    #[test]
+    fn resolves_nested_relative_imports() {
+        let local =
+            |specifier: &str, module: &str| match resolve(specifier, Path::new(module), None, None)
+            {
+                Some(Target::Local(path)) => Some(path),
+                _ => None,
+            };
+
+        assert_eq!(
+            local("./b.js", "src/lib/a.js"),
+            Some(PathBuf::from("src/lib/b.js"))
+        );
+        assert_eq!(
+            local("../../c.js", "src/lib/a.js"),
+            Some(PathBuf::from("c.js"))
+        );
+        assert_eq!(local("../../../d.js", "src/lib/a.js"), None);
+        assert_eq!(local("/e.js", "a.js"), None);
+        assert_eq!(local("lit", "a.js"), None);
+    }
+}